@@ -0,0 +1,517 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use chrono::NaiveTime;
+use hyper::header::HeaderValue;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use thiserror::Error;
+
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/uonetplan/config.toml";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Replaced by `upstream`; only kept to point old configs at its replacement.
+    #[serde(default)]
+    server: Option<String>,
+    /// The city/school symbol used in every uonetplus URL.
+    pub symbol: String,
+    /// The school unit ID used in uonetplus-uczen URLs.
+    pub school_id: String,
+    /// Domain used to build the teachers' e-mail addresses.
+    pub school_mail: String,
+    #[serde(default = "default_bind")]
+    pub bind: SocketAddr,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub upstream: UpstreamConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub calendar: CalendarConfig,
+    #[serde(default)]
+    pub reminders: RemindersConfig,
+    pub profiles: Vec<ProfileConfig>,
+}
+
+/// Settings of the HTTP client shared by all upstream requests. Durations are in seconds.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, default)]
+pub struct HttpConfig {
+    pub connect_timeout: u64,
+    /// Time allowed until the response headers arrive.
+    pub request_timeout: u64,
+    /// How long unused connections are kept open for reuse.
+    pub pool_idle_timeout: u64,
+    pub pool_max_idle_per_host: usize,
+    /// Negotiate HTTP/2 through ALPN.
+    pub http2: bool,
+    pub user_agent: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: 10,
+            request_timeout: 30,
+            pool_idle_timeout: 90,
+            pool_max_idle_per_host: 8,
+            http2: true,
+            user_agent: None,
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout)
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout)
+    }
+
+    pub fn pool_idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.pool_idle_timeout)
+    }
+}
+
+/// How upstream requests that failed with a connection error, a timeout or a server error are retried.
+/// Only GETs and the POSTs that merely read data are retried, never login form posts.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, default)]
+pub struct RetryConfig {
+    /// Attempts per request, including the first one. `1` disables retrying.
+    pub max_attempts: u32,
+    /// Delay before the first retry in milliseconds, doubled for every further one.
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay_ms: 250,
+            max_delay_ms: 4000,
+        }
+    }
+}
+
+/// Stops sending requests to a host that keeps failing, so a Vulcan outage isn't made worse.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, default)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failed attempts after which requests to the host fail immediately.
+    pub failure_threshold: u32,
+    /// Seconds to wait before letting a trial request through again.
+    pub open_duration: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_duration: 60,
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    pub fn open_duration(&self) -> Duration {
+        Duration::from_secs(self.open_duration)
+    }
+}
+
+/// How often the profiles' Vulcan sessions are refreshed. Durations are in seconds.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, default)]
+pub struct SessionConfig {
+    /// Between two refreshes; Vulcan ends sessions that weren't used for about 20 minutes.
+    pub refresh_interval: u64,
+    /// Before the first retry of a failed refresh, doubled for every further one up to `refresh_interval`.
+    pub first_retry_delay: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            refresh_interval: 840,
+            first_retry_delay: 30,
+        }
+    }
+}
+
+impl SessionConfig {
+    pub fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.refresh_interval)
+    }
+
+    pub fn first_retry_delay(&self) -> Duration {
+        Duration::from_secs(self.first_retry_delay)
+    }
+}
+
+/// Which weeks the calendar feeds cover, unless the feed URL asks for others with `past_weeks` and
+/// `future_weeks`. Both count from the current week, which is always included.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, default)]
+pub struct CalendarConfig {
+    pub past_weeks: u32,
+    pub future_weeks: u32,
+    /// The most past or future weeks a feed URL may ask for.
+    pub max_weeks: u32,
+    /// Subjects taking up a whole day or several lessons, like vocational practice or trips, shown as one event
+    /// per day instead of one per lesson. The event is an all-day one if nothing else takes place that day.
+    pub blocks: Vec<String>,
+    /// Joins lessons of the same subject, teacher and room in consecutive slots into one event, unless a feed
+    /// URL asks otherwise with `merge`.
+    pub merge_consecutive: bool,
+    /// The longest break, in minutes, between two lessons that are still joined.
+    pub merge_max_break: u32,
+}
+
+impl Default for CalendarConfig {
+    fn default() -> Self {
+        Self {
+            past_weeks: 0,
+            future_weeks: 2,
+            max_weeks: 12,
+            blocks: Vec::new(),
+            merge_consecutive: false,
+            merge_max_break: 15,
+        }
+    }
+}
+
+/// Alarms added to calendar events, unless a feed URL asks for others. Each one is off when left out.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct RemindersConfig {
+    /// Minutes before the first lesson of each day.
+    pub first_lesson: Option<u32>,
+    /// Minutes before a lesson that's substituted, moved, added or in another room.
+    pub changed_lesson: Option<u32>,
+    /// The time on the day before a test to remind about it, like `19:00`.
+    #[serde(deserialize_with = "deserialize_time")]
+    pub test: Option<NaiveTime>,
+}
+
+/// Parses a time of the day like `19:00`.
+pub fn parse_time(text: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(text, "%H:%M").ok()
+}
+
+fn deserialize_time<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<NaiveTime>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+
+    parse_time(&text)
+        .map(Some)
+        .ok_or_else(|| D::Error::custom(format!("`{text}` is not a time like `19:00`")))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub name: String,
+    /// Left out to discover them from the account; see the `/{profile}/students` endpoint.
+    pub student_id: Option<u32>,
+    pub register_id: Option<u32>,
+    pub school_year: Option<u32>,
+    /// File holding the `EfebSsoCookie`, rewritten on every refresh.
+    pub cookie_file: PathBuf,
+    /// Credentials used to log in when there is no usable cookie.
+    pub login: Option<String>,
+    pub password: Option<String>,
+    /// Shell command printing a fresh cookie, run when the session expires and there are no credentials.
+    pub recovery_command: Option<String>,
+    /// File keeping the calendar events' revisions, so their `SEQUENCE` numbers survive restarts.
+    pub revisions_file: Option<PathBuf>,
+    /// Lessons left out of the profile's calendars, unless a feed URL includes them explicitly.
+    #[serde(default)]
+    pub exclude: ExcludeConfig,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, default)]
+pub struct ExcludeConfig {
+    pub subjects: Vec<String>,
+    /// As `Surname Name` or `Name Surname`.
+    pub teachers: Vec<String>,
+    pub rooms: Vec<String>,
+}
+
+impl ProfileConfig {
+    /// Whether the student, register or school year has to be discovered from the account.
+    pub fn needs_discovery(&self) -> bool {
+        self.student_id.is_none() || self.register_id.is_none() || self.school_year.is_none()
+    }
+
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        Some((self.login.as_deref()?, self.password.as_deref()?))
+    }
+}
+
+/// Where upstream requests are sent and how the servers are verified.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, default)]
+pub struct UpstreamConfig {
+    /// Connects to these IP addresses instead of looking the hostnames up. The certificate is still
+    /// verified against the hostname.
+    pub resolve: HashMap<String, IpAddr>,
+    /// SHA-256 fingerprints (hex, colons allowed) of the server certificates to accept, checked on top
+    /// of the regular verification.
+    pub pinned_certificates: Vec<String>,
+    /// Sends every request to this URL instead, keeping the real `Host` header. Only meant for a local
+    /// mock server.
+    pub base_url: Option<String>,
+    /// Answers requests from this JSON file of canned responses instead of sending them anywhere.
+    pub canned_responses: Option<PathBuf>,
+    /// Records every response (with cookies and tokens redacted) into this file, in the format
+    /// `canned_responses` replays.
+    pub record: Option<PathBuf>,
+}
+
+impl UpstreamConfig {
+    pub fn pins(&self) -> Vec<[u8; 32]> {
+        self.pinned_certificates
+            .iter()
+            .filter_map(|pin| parse_fingerprint(pin))
+            .collect()
+    }
+}
+
+fn parse_fingerprint(fingerprint: &str) -> Option<[u8; 32]> {
+    let hex = fingerprint.replace(':', "");
+
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut bytes = [0; 32];
+
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+
+    Some(bytes)
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read config file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse config file {path}: {message}")]
+    Parse { path: PathBuf, message: String },
+    #[error("`{0}` must not be empty")]
+    Empty(String),
+    #[error(
+        "`server` was removed: requests go to the Vulcan hostnames with certificate verification now, \
+        set `upstream.resolve` to connect to fixed IP addresses"
+    )]
+    ServerRemoved,
+    #[error("`upstream.base_url` must be an http(s) URL without a trailing slash, got `{0}`")]
+    InvalidBaseUrl(String),
+    #[error("`upstream.{0}` and `upstream.{1}` can't be used together")]
+    ConflictingUpstream(&'static str, &'static str),
+    #[error("`{0}` in `upstream.pinned_certificates` is not a SHA-256 fingerprint")]
+    InvalidPin(String),
+    #[error("at least one profile has to be configured")]
+    NoProfiles,
+    #[error("profile name `{0}` is used more than once")]
+    DuplicateProfile(String),
+    #[error("profile name `{0}` may only contain letters, digits, `-` and `_`")]
+    InvalidProfileName(String),
+    #[error("profile `{0}` has to set both `login` and `password`, or neither")]
+    IncompleteCredentials(String),
+    #[error("`http.{0}` must be greater than zero")]
+    ZeroTimeout(&'static str),
+    #[error("`{0}` must be greater than zero")]
+    Zero(&'static str),
+    #[error("`calendar.{0}` can't be more than `calendar.max_weeks`")]
+    TooManyWeeks(&'static str),
+    #[error("`http.user_agent` is not a valid header value")]
+    InvalidUserAgent,
+}
+
+fn default_bind() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 8080))
+}
+
+impl Config {
+    /// Reads the config from `path`. Files ending in `.json` are parsed as JSON, anything else as TOML.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+
+        let parse_error = |message: String| ConfigError::Parse {
+            path: path.to_owned(),
+            message,
+        };
+
+        let config: Config = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&text).map_err(|err| parse_error(err.to_string())),
+            _ => toml::from_str(&text).map_err(|err| parse_error(err.to_string())),
+        }?;
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.is_some() {
+            return Err(ConfigError::ServerRemoved);
+        }
+
+        if let Some(base_url) = &self.upstream.base_url {
+            if !(base_url.starts_with("https://") || base_url.starts_with("http://"))
+                || base_url.ends_with('/')
+            {
+                return Err(ConfigError::InvalidBaseUrl(base_url.clone()));
+            }
+        }
+
+        if self.upstream.canned_responses.is_some() {
+            if self.upstream.base_url.is_some() {
+                return Err(ConfigError::ConflictingUpstream(
+                    "base_url",
+                    "canned_responses",
+                ));
+            }
+
+            if self.upstream.record.is_some() {
+                return Err(ConfigError::ConflictingUpstream(
+                    "canned_responses",
+                    "record",
+                ));
+            }
+        }
+
+        for pin in &self.upstream.pinned_certificates {
+            if parse_fingerprint(pin).is_none() {
+                return Err(ConfigError::InvalidPin(pin.clone()));
+            }
+        }
+
+        for (field, value) in [
+            ("symbol", &self.symbol),
+            ("school_id", &self.school_id),
+            ("school_mail", &self.school_mail),
+        ] {
+            if value.trim().is_empty() {
+                return Err(ConfigError::Empty(field.to_owned()));
+            }
+        }
+
+        for (field, value) in [
+            ("connect_timeout", self.http.connect_timeout),
+            ("request_timeout", self.http.request_timeout),
+        ] {
+            if value == 0 {
+                return Err(ConfigError::ZeroTimeout(field));
+            }
+        }
+
+        for (field, value) in [
+            ("session.refresh_interval", self.session.refresh_interval),
+            ("session.first_retry_delay", self.session.first_retry_delay),
+        ] {
+            if value == 0 {
+                return Err(ConfigError::Zero(field));
+            }
+        }
+
+        for (field, value) in [
+            ("retry.max_attempts", self.retry.max_attempts),
+            (
+                "circuit_breaker.failure_threshold",
+                self.circuit_breaker.failure_threshold,
+            ),
+        ] {
+            if value == 0 {
+                return Err(ConfigError::Zero(field));
+            }
+        }
+
+        for (field, value) in [
+            ("past_weeks", self.calendar.past_weeks),
+            ("future_weeks", self.calendar.future_weeks),
+        ] {
+            if value > self.calendar.max_weeks {
+                return Err(ConfigError::TooManyWeeks(field));
+            }
+        }
+
+        if let Some(user_agent) = &self.http.user_agent {
+            if HeaderValue::from_str(user_agent).is_err() {
+                return Err(ConfigError::InvalidUserAgent);
+            }
+        }
+
+        if self.profiles.is_empty() {
+            return Err(ConfigError::NoProfiles);
+        }
+
+        let mut names = HashSet::new();
+
+        for profile in &self.profiles {
+            if profile.name.is_empty()
+                || !profile
+                    .name
+                    .chars()
+                    .all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '_')
+            {
+                return Err(ConfigError::InvalidProfileName(profile.name.clone()));
+            }
+
+            if !names.insert(&profile.name) {
+                return Err(ConfigError::DuplicateProfile(profile.name.clone()));
+            }
+
+            if profile.cookie_file.as_os_str().is_empty() {
+                return Err(ConfigError::Empty(format!(
+                    "profiles.{}.cookie_file",
+                    profile.name
+                )));
+            }
+
+            if profile.login.is_some() != profile.password.is_some() {
+                return Err(ConfigError::IncompleteCredentials(profile.name.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Makes `config` available through [`get`]. Must be called exactly once, before anything reads it.
+pub fn init(config: Config) {
+    if CONFIG.set(config).is_err() {
+        panic!("Config initialized twice.");
+    }
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get().expect("Config read before initialization.")
+}
@@ -1,83 +1,294 @@
-use std::{
-    fs::File,
-    io::Write,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use hyper::HeaderMap;
+use chrono::{DateTime, Local};
+use hyper::header;
+use serde::Serialize;
 use tokio::task::JoinHandle;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    config::{self, ProfileConfig},
+    error::Error,
+    profiles::{self, Profile},
+    requests::{self, AuthInfo},
+};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    /// The last refresh (or the startup) succeeded.
+    Active,
+    /// Refreshing failed for a reason other than an expired session, e.g. Vulcan being down.
+    Failing,
+    /// The session expired and could not be recovered.
+    Expired,
+    /// Vulcan rejected the login and password; refreshing stopped until they're fixed and the service restarted.
+    InvalidCredentials,
+}
 
-use crate::requests::{self, AuthInfo, GROUP_ONE_AUTH, GROUP_TWO_AUTH};
+#[derive(Serialize, Debug)]
+pub struct SessionStatus {
+    pub state: SessionState,
+    pub last_refreshed: Option<DateTime<Local>>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    pub next_attempt: Option<DateTime<Local>>,
+}
+
+impl Default for SessionStatus {
+    fn default() -> Self {
+        Self {
+            state: SessionState::Active,
+            last_refreshed: None,
+            last_error: None,
+            consecutive_failures: 0,
+            next_attempt: None,
+        }
+    }
+}
+
+impl SessionStatus {
+    fn succeeded(&mut self) {
+        self.state = SessionState::Active;
+        self.last_refreshed = Some(Local::now());
+        self.last_error = None;
+        self.consecutive_failures = 0;
+    }
+
+    fn failed(&mut self, err: &anyhow::Error) {
+        self.state = if Error::is_session_expired(err) {
+            SessionState::Expired
+        } else {
+            SessionState::Failing
+        };
+        self.last_error = Some(format!("{err:#}"));
+        self.consecutive_failures += 1;
+    }
+
+    /// Doubles the delay with every failure, never waiting longer than a regular refresh.
+    fn retry_delay(&self) -> Duration {
+        let config = &config::get().session;
+        let exponent = self.consecutive_failures.saturating_sub(1).min(16);
+
+        (config.first_retry_delay() * 2u32.pow(exponent)).min(config.refresh_interval())
+    }
+}
 
 pub fn spawn_refresher() -> JoinHandle<Result<()>> {
     tokio::spawn(async {
         println!("Refresh task started.");
 
-        let mut interval = tokio::time::interval(Duration::from_secs(840));
-
-        loop {
-            interval.tick().await;
-
-            async fn refresh(auth_info: &mut AuthInfo, file_name: &str) -> Result<()> {
-                println!("Refreshing cookie...");
-
-                let mut headers = HeaderMap::new();
-
-                headers.append("X-V-AppGuid", "2921bc596ec7b32f42a75a1e117ce40a".parse()?);
-                headers.append("X-V-AppVersion", "22.08.0007.53443".parse()?);
-                headers.append("X-V-RequestVerificationToken", "3GqwR36JjwPdGzir5dXSvAAJ14u7VpOenTcGKGak9JhAAyZQwiRswRISo_BbA5PDwHlLdHiqlt7BZ_6AB7KdygalChDHJhH7MkL_Bd4XnjM3kEm20".parse()?);
-
-                let resp = requests::get(
-                    format!(
-                        "/{}/{}/Home.mvc/RefreshSession?_dc={}",
-                        std::env::var("SYMBOL").unwrap(),
-                        std::env::var("STUDENT_ID").unwrap(),
-                        SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis()
-                    ),
-                    &auth_info,
-                    requests::Host::UonetPlusUczen,
-                    Some(headers),
-                )
-                .await?;
-
-                let set_cookie = resp
-                    .headers()
-                    .get("set-cookie")
-                    .context("Set-Cookie not received")?;
-
-                for res_cookie in cookie::Cookie::split_parse(set_cookie.to_str()?) {
-                    if let Ok(res_cookie) = res_cookie {
-                        if res_cookie.name() == "EfebSsoCookie" {
-                            if res_cookie.value() == "null" {
-                                panic!("Failed to refresh cookie.");
-                            }
-
-                            auth_info.cookie = res_cookie.value().to_owned();
-                            if let Ok(mut file) = File::create(file_name) {
-                                if let Err(err) = file.write_all(res_cookie.value().as_bytes()) {
-                                    eprintln!("Failed to write to cookie file: {err:#?}");
-                                };
-                            }
-
-                            println!("Refreshed cookie: {}", auth_info.cookie);
-                            break;
-                        }
-                    }
-                }
+        let tasks = profiles::all()
+            .map(|profile| tokio::spawn(refresh_loop(profile)))
+            .collect::<Vec<_>>();
+
+        for task in tasks {
+            task.await?;
+        }
 
-                Ok(())
+        Ok(())
+    })
+}
+
+async fn refresh_loop(profile: &'static Profile) {
+    let mut delay = Duration::ZERO;
+
+    loop {
+        tokio::time::sleep(delay).await;
+
+        println!("Refreshing cookie for profile {}...", profile.name());
+
+        let result = refresh_session(profile).await;
+
+        if result.is_ok() && profile.config.needs_discovery() {
+            // Picks up a new school year without a restart.
+            let mut auth = profile.auth.lock().await;
+
+            if let Err(err) = profiles::discover(profile.config, &mut auth).await {
+                eprintln!(
+                    "Failed to rediscover the students of profile {}: {err:#}",
+                    profile.name()
+                );
             }
+        }
 
-            {
-                let mut auth = GROUP_ONE_AUTH.lock().await;
-                refresh(&mut auth, "/etc/uonetplan/cookie_1").await.unwrap();
+        let mut status = profile.session.lock().await;
+
+        delay = match result {
+            Ok(()) => {
+                status.succeeded();
+                config::get().session.refresh_interval()
+            }
+            Err(err) if matches!(Error::classify(&err), Error::InvalidCredentials(_)) => {
+                // Logging in again and again with a wrong password could get the account locked.
+                eprintln!(
+                    "Stopped refreshing the session of profile {}, fix its login and password and restart: {err:#}",
+                    profile.name()
+                );
+                status.state = SessionState::InvalidCredentials;
+                status.last_error = Some(format!("{err:#}"));
+                status.next_attempt = None;
+
+                return;
             }
-            {
-                let mut auth = GROUP_TWO_AUTH.lock().await;
-                refresh(&mut auth, "/etc/uonetplan/cookie_2").await.unwrap();
+            Err(err) => {
+                eprintln!(
+                    "Failed to refresh the session of profile {}: {err:#}",
+                    profile.name()
+                );
+                status.failed(&err);
+                status.retry_delay()
             }
+        };
+
+        status.next_attempt = chrono::Duration::from_std(delay)
+            .ok()
+            .map(|delay| Local::now() + delay);
+    }
+}
+
+/// Refreshes the session, recovering it if Vulcan reports it as expired.
+async fn refresh_session(profile: &Profile) -> Result<()> {
+    let mut auth = profile.auth.lock().await;
+
+    let err = match refresh(&mut auth, profile.config).await {
+        Err(err) if Error::is_session_expired(&err) => err,
+        result => return result,
+    };
+
+    eprintln!(
+        "Session of profile {} expired ({err:#}), recovering...",
+        profile.name()
+    );
+
+    profile.session.lock().await.state = SessionState::Expired;
+
+    let recovered = async {
+        recover(&mut auth, profile.config).await?;
+        refresh(&mut auth, profile.config).await
+    };
+
+    match recovered.await {
+        Ok(()) => {
+            println!("Recovered the session of profile {}.", profile.name());
+            Ok(())
         }
-    })
+        Err(recovery_err)
+            if matches!(Error::classify(&recovery_err), Error::InvalidCredentials(_)) =>
+        {
+            Err(recovery_err)
+        }
+        Err(recovery_err) => Err(err.context(format!("recovery failed: {recovery_err:#}"))),
+    }
+}
+
+async fn refresh(auth_info: &mut AuthInfo, profile: &ProfileConfig) -> Result<()> {
+    // Vulcan deployments change these, so they're re-read on every refresh.
+    auth_info.app_params = Some(
+        requests::app_params::discover(auth_info)
+            .await
+            .context("Failed to discover the app parameters")?,
+    );
+
+    let resp = requests::get(
+        format!(
+            "/{}/{}/Home.mvc/RefreshSession?_dc={}",
+            config::get().symbol,
+            config::get().school_id,
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis()
+        ),
+        auth_info,
+        requests::Host::UonetPlusUczen,
+        None,
+    )
+    .await?;
+
+    if resp.status().is_redirection() {
+        let location = resp
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .unwrap_or_default();
+
+        return Err(Error::SessionExpired(format!("redirected to {location}")).into());
+    }
+
+    let mut new_cookie = None;
+
+    for set_cookie in resp.headers().get_all(header::SET_COOKIE) {
+        for res_cookie in cookie::Cookie::split_parse(set_cookie.to_str()?).flatten() {
+            if res_cookie.name() == "EfebSsoCookie" {
+                if res_cookie.value() == "null" {
+                    return Err(
+                        Error::SessionExpired("EfebSsoCookie was cleared".to_owned()).into(),
+                    );
+                }
+
+                new_cookie = Some(res_cookie.value().to_owned());
+            }
+        }
+    }
+
+    let status = resp.status();
+    let body = requests::body_text(resp.into_body()).await?;
+
+    if serde_json::from_str::<serde_json::Value>(&body).is_err() {
+        if body.trim_start().starts_with('<') {
+            return Err(Error::SessionExpired("received HTML instead of JSON".to_owned()).into());
+        }
+
+        bail!("Unexpected session refresh response ({status})");
+    }
+
+    if let Some(cookie) = new_cookie {
+        profiles::write_cookie(profile, &cookie);
+        auth_info.cookie = cookie;
+
+        println!("Refreshed cookie for profile {}.", profile.name);
+    }
+
+    Ok(())
+}
+
+/// Obtains a new cookie by logging in, running the recovery command, or re-reading a cookie file
+/// the operator replaced by hand, in that order of preference.
+pub async fn recover(auth_info: &mut AuthInfo, profile: &ProfileConfig) -> Result<()> {
+    let cookie = if let Some((login, password)) = profile.credentials() {
+        requests::login::log_in(login, password).await?
+    } else if let Some(command) = &profile.recovery_command {
+        let output = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .await
+            .context("Failed to run the recovery command")?;
+
+        if !output.status.success() {
+            bail!("Recovery command exited with {}", output.status);
+        }
+
+        String::from_utf8(output.stdout)?
+            .lines()
+            .next()
+            .filter(|cookie| !cookie.is_empty())
+            .context("Recovery command didn't print a cookie")?
+            .to_owned()
+    } else {
+        let cookie = profiles::read_cookie(profile)?;
+
+        if cookie == auth_info.cookie {
+            bail!(
+                "no credentials or recovery command configured, and {} wasn't replaced",
+                profile.cookie_file.display()
+            );
+        }
+
+        cookie
+    };
+
+    profiles::write_cookie(profile, &cookie);
+    auth_info.cookie = cookie;
+
+    Ok(())
 }
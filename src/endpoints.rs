@@ -1,10 +1,14 @@
 use anyhow::{Context, Result};
 use chrono::NaiveDate;
 use hyper::HeaderMap;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::requests::{self, AuthInfo};
+use crate::{
+    config,
+    error::Error,
+    requests::{self, AuthInfo},
+};
 
 #[derive(Deserialize, Debug)]
 pub struct WeekPlanResponse {
@@ -14,8 +18,6 @@ pub struct WeekPlanResponse {
 
 #[derive(Deserialize, Debug)]
 pub struct WeekPlanData {
-    #[serde(rename = "Data")]
-    pub date: String,
     #[serde(rename = "Headers")]
     pub headers: Vec<WeekPlanHeader>,
     #[serde(rename = "Rows")]
@@ -33,18 +35,108 @@ pub async fn get_week_plan(day: NaiveDate, auth_info: &AuthInfo) -> Result<WeekP
     headers.insert("Content-Type", "application/json".try_into()?);
 
     let res = requests::post(
-        format!("/{}/{}/PlanZajec.mvc/Get", std::env::var("SYMBOL").unwrap(), std::env::var("STUDENT_ID").unwrap()),
+        format!(
+            "/{}/{}/PlanZajec.mvc/Get",
+            config::get().symbol,
+            config::get().school_id
+        ),
         auth_info,
         requests::Host::UonetPlusUczen,
-        Some(
-            json!({ "data": format!("{}T00:00:00", day.format("%Y-%m-%d").to_string()) })
-                .to_string(),
-        ),
+        Some(json!({ "data": format!("{}T00:00:00", day.format("%Y-%m-%d")) }).to_string()),
         Some(headers),
     )
     .await?;
 
-    let body = requests::body_text(res.into_body()).await?;
+    let response = requests::read_json::<WeekPlanResponse>(res)
+        .await
+        .context("Failed to parse response data.")?;
+
+    if !response.success {
+        return Err(
+            Error::UpstreamFormat("week plan request was not successful".to_owned()).into(),
+        );
+    }
+
+    Ok(response)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DiariesResponse {
+    pub success: bool,
+    pub data: Vec<Diary>,
+}
+
+/// A student's register (dziennik) in one school year, as listed by `UczenDziennik.mvc/Get`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Diary {
+    #[serde(rename(deserialize = "IdUczen"))]
+    pub student_id: u32,
+    #[serde(rename(deserialize = "UczenImie"), default)]
+    pub first_name: String,
+    #[serde(rename(deserialize = "UczenNazwisko"), default)]
+    pub last_name: String,
+    #[serde(rename(deserialize = "IdDziennik"))]
+    pub register_id: u32,
+    #[serde(rename(deserialize = "DziennikRokSzkolny"))]
+    pub school_year: u32,
+    #[serde(rename(deserialize = "Poziom"), default)]
+    pub level: Option<u32>,
+    #[serde(rename(deserialize = "Symbol"), default)]
+    pub symbol: Option<String>,
+    #[serde(rename(deserialize = "DziennikDataOd"), default)]
+    pub start: Option<String>,
+    #[serde(rename(deserialize = "DziennikDataDo"), default)]
+    pub end: Option<String>,
+}
+
+impl Diary {
+    fn date(value: &Option<String>) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(value.as_ref()?.get(..10)?, "%Y-%m-%d").ok()
+    }
+
+    pub fn start_date(&self) -> Option<NaiveDate> {
+        Self::date(&self.start)
+    }
+
+    pub fn end_date(&self) -> Option<NaiveDate> {
+        Self::date(&self.end)
+    }
+
+    /// Whether `day` falls into this diary's school year.
+    pub fn contains(&self, day: NaiveDate) -> bool {
+        match (self.start_date(), self.end_date()) {
+            (Some(start), Some(end)) => start <= day && day <= end,
+            _ => false,
+        }
+    }
+
+    pub fn class_name(&self) -> Option<String> {
+        Some(format!("{}{}", self.level?, self.symbol.as_deref()?))
+    }
+}
+
+/// Lists every student and register the account has access to.
+pub async fn get_diaries(auth_info: &AuthInfo) -> Result<Vec<Diary>> {
+    let res = requests::post(
+        format!(
+            "/{}/{}/UczenDziennik.mvc/Get",
+            config::get().symbol,
+            config::get().school_id
+        ),
+        auth_info,
+        requests::Host::UonetPlusUczen,
+        Option::<hyper::Body>::None,
+        None,
+    )
+    .await?;
+
+    let response = requests::read_json::<DiariesResponse>(res)
+        .await
+        .context("Failed to parse diaries.")?;
+
+    if !response.success {
+        return Err(Error::UpstreamFormat("diaries request was not successful".to_owned()).into());
+    }
 
-    serde_json::from_str::<WeekPlanResponse>(&body).context("Failed to parse response data.")
+    Ok(response.data)
 }
@@ -0,0 +1,140 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::{requests::login::LoginError, week_plan::ParseError};
+
+/// Errors as reported to API clients. Anything else is classified by [`Error::classify`].
+#[derive(Error, Debug, Clone)]
+pub enum Error {
+    /// Vulcan no longer accepts the profile's session; the refresher is trying to recover it.
+    #[error("the Vulcan session expired: {0}")]
+    SessionExpired(String),
+    /// Vulcan rejected the profile's login and password. Trying again won't help until they're fixed.
+    #[error("Vulcan rejected the login or password: {0}")]
+    InvalidCredentials(String),
+    /// Vulcan couldn't be reached, timed out or answered with a server error.
+    #[error("Vulcan is unavailable: {0}")]
+    UpstreamUnavailable(String),
+    /// Vulcan answered, but not in the format we expect.
+    #[error("Vulcan returned data in an unexpected format: {0}")]
+    UpstreamFormat(String),
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("unknown profile `{0}`")]
+    UnknownProfile(String),
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl Error {
+    /// Finds the most specific error in `err`'s chain, falling back to [`Error::Internal`].
+    pub fn classify(err: &anyhow::Error) -> Self {
+        let message = format!("{err:#}");
+
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<Error>() {
+                return err.clone();
+            }
+
+            if let Some(err) = cause.downcast_ref::<LoginError>() {
+                return match err {
+                    LoginError::InvalidCredentials(_) => Error::InvalidCredentials(message),
+                    _ => Error::SessionExpired(message),
+                };
+            }
+
+            // Connection errors of the HTTP client come wrapped in a `hyper::Error`, which is found first. A bare
+            // `std::io::Error` is a local one, like a missing cookie file, and left to `Internal`.
+            if cause.is::<hyper::Error>() || cause.is::<tokio::time::error::Elapsed>() {
+                return Error::UpstreamUnavailable(message);
+            }
+
+            if cause.is::<serde_json::Error>()
+                || cause.is::<html_parser::Error>()
+                || cause.is::<ParseError>()
+            {
+                return Error::UpstreamFormat(message);
+            }
+        }
+
+        Error::Internal(message)
+    }
+
+    pub fn is_session_expired(err: &anyhow::Error) -> bool {
+        matches!(Self::classify(err), Error::SessionExpired(_))
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Error::SessionExpired(_) => "session_expired",
+            Error::InvalidCredentials(_) => "invalid_credentials",
+            Error::UpstreamUnavailable(_) => "upstream_unavailable",
+            Error::UpstreamFormat(_) => "upstream_format",
+            Error::BadRequest(_) => "bad_request",
+            Error::UnknownProfile(_) => "unknown_profile",
+            Error::Internal(_) => "internal",
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        Self::classify(&err)
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::SessionExpired(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::UpstreamUnavailable(_) | Error::UpstreamFormat(_) => StatusCode::BAD_GATEWAY,
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::UnknownProfile(_) => StatusCode::NOT_FOUND,
+            Error::InvalidCredentials(_) | Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.status_code().is_server_error() {
+            eprintln!("{self}");
+        }
+
+        HttpResponse::build(self.status_code()).json(json!({
+            "error": self.code(),
+            "message": self.to_string(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn tells_invalid_credentials_apart_from_an_expired_session() {
+        let err = anyhow::Error::from(LoginError::InvalidCredentials("Zła nazwa".to_owned()))
+            .context("Failed to log in");
+
+        assert!(matches!(
+            Error::classify(&err),
+            Error::InvalidCredentials(_)
+        ));
+        assert!(!Error::is_session_expired(&err));
+
+        let err = anyhow::Error::from(LoginError::TooManySteps);
+
+        assert!(Error::is_session_expired(&err));
+    }
+
+    #[test]
+    fn leaves_local_io_errors_internal() {
+        let err = std::fs::read_to_string("/nonexistent/uonetplan/cookie")
+            .context("Failed to read the cookie file")
+            .unwrap_err();
+
+        assert!(matches!(Error::classify(&err), Error::Internal(_)));
+    }
+}
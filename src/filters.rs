@@ -0,0 +1,249 @@
+//! Lets every subscriber tailor a calendar feed with query parameters, on top of the exclusions configured for
+//! the profile. Names are compared case-insensitively; teachers match in either name order.
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    config::ExcludeConfig,
+    week_plan::{Lesson, LessonStatus},
+};
+
+/// A comma-separated list of names in a query parameter, like `Religia,Wychowanie fizyczne`.
+#[derive(Default, Debug, Clone)]
+pub struct List(pub Vec<String>);
+
+impl<'de> Deserialize<'de> for List {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+
+        Ok(Self(
+            text.split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        ))
+    }
+}
+
+/// Subjects, teachers and rooms a lesson is checked against.
+#[derive(Default, Debug, Clone)]
+pub struct Criteria {
+    pub subjects: Vec<String>,
+    pub teachers: Vec<String>,
+    pub rooms: Vec<String>,
+}
+
+impl Criteria {
+    fn subject(&self, lesson: &Lesson) -> bool {
+        contains(&self.subjects, &lesson.subject)
+    }
+
+    fn teacher(&self, lesson: &Lesson) -> bool {
+        lesson
+            .teacher
+            .as_deref()
+            .is_some_and(|teacher| contains_teacher(&self.teachers, teacher))
+    }
+
+    fn room(&self, lesson: &Lesson) -> bool {
+        lesson
+            .room
+            .as_deref()
+            .is_some_and(|room| contains(&self.rooms, room))
+    }
+}
+
+/// Whether `names` contains `name`, regardless of case.
+pub fn contains(names: &[String], name: &str) -> bool {
+    names
+        .iter()
+        .any(|other| other.to_lowercase() == name.to_lowercase())
+}
+
+/// Whether `teachers` contains `teacher` in either name order, regardless of case.
+fn contains_teacher(teachers: &[String], teacher: &str) -> bool {
+    let reversed = teacher.rsplit(' ').collect::<Vec<_>>().join(" ");
+
+    contains(teachers, teacher) || contains(teachers, &reversed)
+}
+
+#[derive(Debug)]
+pub struct Filter {
+    /// Lessons have to match every non-empty list.
+    pub include: Criteria,
+    /// Lessons matching any entry are left out.
+    pub exclude: Criteria,
+    /// Leaves out lessons without any changes.
+    pub only_changed: bool,
+    pub hide_cancelled: bool,
+}
+
+impl Filter {
+    /// Adds the profile's exclusions to `exclude`, except for the names `include` asks for explicitly.
+    pub fn new(
+        include: Criteria,
+        mut exclude: Criteria,
+        defaults: &ExcludeConfig,
+        only_changed: bool,
+        hide_cancelled: bool,
+    ) -> Self {
+        for (excluded, default, included) in [
+            (&mut exclude.subjects, &defaults.subjects, &include.subjects),
+            (&mut exclude.rooms, &defaults.rooms, &include.rooms),
+        ] {
+            excluded.extend(
+                default
+                    .iter()
+                    .filter(|name| !contains(included, name))
+                    .cloned(),
+            );
+        }
+
+        // Teachers named in the other order cancel their exclusion too.
+        exclude.teachers.extend(
+            defaults
+                .teachers
+                .iter()
+                .filter(|name| !contains_teacher(&include.teachers, name))
+                .cloned(),
+        );
+
+        Self {
+            include,
+            exclude,
+            only_changed,
+            hide_cancelled,
+        }
+    }
+
+    pub fn matches(&self, lesson: &Lesson) -> bool {
+        if self.only_changed && lesson.changes.is_empty()
+            || self.hide_cancelled && lesson.status == LessonStatus::Cancelled
+        {
+            return false;
+        }
+
+        let included = (self.include.subjects.is_empty() || self.include.subject(lesson))
+            && (self.include.teachers.is_empty() || self.include.teacher(lesson))
+            && (self.include.rooms.is_empty() || self.include.room(lesson));
+
+        let excluded = self.exclude.subject(lesson)
+            || self.exclude.teacher(lesson)
+            || self.exclude.room(lesson);
+
+        included && !excluded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing,
+        week_plan::{Change, Lesson},
+    };
+
+    fn lesson(subject: &str, teacher: &str, room: &str) -> Lesson {
+        let mut lesson = testing::lesson(testing::date(10, 12), 1, subject);
+        lesson.teacher = Some(teacher.to_owned());
+        lesson.room = Some(room.to_owned());
+        lesson
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| (*name).to_owned()).collect()
+    }
+
+    fn filter(include: Criteria, exclude: Criteria, defaults: &ExcludeConfig) -> Filter {
+        Filter::new(include, exclude, defaults, false, false)
+    }
+
+    #[test]
+    fn includes_only_lessons_matching_every_list() {
+        let include = Criteria {
+            subjects: names(&["matematyka", "Fizyka"]),
+            rooms: names(&["12"]),
+            ..Default::default()
+        };
+        let filter = filter(include, Criteria::default(), &ExcludeConfig::default());
+
+        assert!(filter.matches(&lesson("Matematyka", "Kowalski Jan", "12")));
+        assert!(!filter.matches(&lesson("Matematyka", "Kowalski Jan", "7")));
+        assert!(!filter.matches(&lesson("Chemia", "Kowalski Jan", "12")));
+    }
+
+    #[test]
+    fn matches_teachers_in_either_name_order() {
+        let exclude = Criteria {
+            teachers: names(&["jan kowalski"]),
+            ..Default::default()
+        };
+        let filter = filter(Criteria::default(), exclude, &ExcludeConfig::default());
+
+        assert!(!filter.matches(&lesson("Matematyka", "Kowalski Jan", "12")));
+        assert!(filter.matches(&lesson("Matematyka", "Nowak Anna", "12")));
+    }
+
+    #[test]
+    fn includes_cancel_the_profile_exclusions() {
+        let defaults = ExcludeConfig {
+            subjects: names(&["Religia", "Praktyka zawodowa"]),
+            teachers: names(&["Kowalski Jan"]),
+            rooms: Vec::new(),
+        };
+
+        let religion = lesson("Religia", "Piotrowski Józef", "2");
+        let practice = lesson("Praktyka zawodowa", "Kamiński Adam", "P1");
+        let mathematics = lesson("Matematyka", "Kowalski Jan", "12");
+
+        let defaults_only = filter(Criteria::default(), Criteria::default(), &defaults);
+        assert!(!defaults_only.matches(&religion));
+        assert!(!defaults_only.matches(&practice));
+        assert!(!defaults_only.matches(&mathematics));
+
+        let include = Criteria {
+            subjects: names(&["religia"]),
+            ..Default::default()
+        };
+        let with_religion = filter(include, Criteria::default(), &defaults);
+        assert!(with_religion.matches(&religion));
+        assert!(!with_religion.matches(&practice));
+
+        let include = Criteria {
+            teachers: names(&["Jan Kowalski"]),
+            ..Default::default()
+        };
+        assert!(filter(include, Criteria::default(), &defaults).matches(&mathematics));
+    }
+
+    #[test]
+    fn combines_only_changed_and_hide_cancelled() {
+        let regular = lesson("Matematyka", "Kowalski Jan", "12");
+
+        let mut cancelled = regular.clone();
+        cancelled.status = LessonStatus::Cancelled;
+        cancelled.changes = vec![Change::Cancelled { reason: None }];
+
+        let mut substitution = regular.clone();
+        substitution.status = LessonStatus::Substitution;
+        substitution.changes = vec![Change::Added];
+
+        let matching = |only_changed, hide_cancelled| {
+            let filter = Filter::new(
+                Criteria::default(),
+                Criteria::default(),
+                &ExcludeConfig::default(),
+                only_changed,
+                hide_cancelled,
+            );
+
+            [&regular, &cancelled, &substitution].map(|lesson| filter.matches(lesson))
+        };
+
+        assert_eq!(matching(false, false), [true, true, true]);
+        assert_eq!(matching(true, false), [false, true, true]);
+        assert_eq!(matching(false, true), [true, false, true]);
+        assert_eq!(matching(true, true), [false, false, true]);
+    }
+}
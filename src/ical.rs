@@ -0,0 +1,79 @@
+//! Helpers for writing the iCalendar feeds. Lesson times are Polish wall-clock times, so events carry
+//! `TZID=Europe/Warsaw` and every calendar defines that zone, leaving the DST switches to the calendar apps.
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use ics::{
+    components::{Parameter, Property},
+    properties::{Description, Trigger},
+    Alarm, Daylight, ICalendar, Standard, TimeZone,
+};
+
+pub const TZID: &str = "Europe/Warsaw";
+
+/// An empty calendar with the Europe/Warsaw time zone defined.
+pub fn new<'a>() -> ICalendar<'a> {
+    let mut calendar = ICalendar::new("2.0", "ics-rs");
+    calendar.push(Property::new("X-WR-TIMEZONE", TZID));
+    calendar.add_timezone(timezone());
+
+    calendar
+}
+
+/// Europe/Warsaw as observed since 1996: CET, with CEST from the last Sunday of March to the last Sunday of
+/// October.
+fn timezone<'a>() -> TimeZone<'a> {
+    let mut daylight = Daylight::new("19700329T020000", "+0100", "+0200");
+    daylight.push(Property::new("TZNAME", "CEST"));
+    daylight.push(Property::new("RRULE", "FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU"));
+
+    let mut standard = Standard::new("19701025T030000", "+0200", "+0100");
+    standard.push(Property::new("TZNAME", "CET"));
+    standard.push(Property::new("RRULE", "FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU"));
+
+    let mut timezone = TimeZone::daylight(TZID, daylight);
+    timezone.add_standard(standard);
+
+    timezone
+}
+
+/// A `DTSTART`/`DTEND`-like property with a Warsaw wall-clock time.
+pub fn local_time<'a>(name: &'a str, date: NaiveDate, time: NaiveTime) -> Property<'a> {
+    let mut property = Property::new(
+        name,
+        date.and_time(time).format("%Y%m%dT%H%M%S").to_string(),
+    );
+    property.add(Parameter::new("TZID", TZID));
+
+    property
+}
+
+/// A `DTSTART`/`DTEND`-like property of an all-day event.
+pub fn date<'a>(name: &'a str, date: NaiveDate) -> Property<'a> {
+    let mut property = Property::new(name, date.format("%Y%m%d").to_string());
+    property.add(Parameter::new("VALUE", "DATE"));
+
+    property
+}
+
+/// An `RRULE` repeating an event every week, up to and including `last_day`.
+pub fn weekly_until<'a>(last_day: NaiveDate) -> Property<'a> {
+    // With a TZID on DTSTART, UNTIL has to be in UTC. 21:59:59 UTC is still `last_day` in Warsaw, in summer and
+    // winter alike, and later than any lesson ends.
+    Property::new(
+        "RRULE",
+        format!("FREQ=WEEKLY;UNTIL={}T215959Z", last_day.format("%Y%m%d")),
+    )
+}
+
+/// A notification `minutes_before` the event starts.
+pub fn alarm<'a>(minutes_before: u32, description: String) -> Alarm<'a> {
+    Alarm::display(
+        Trigger::new(format!("-PT{minutes_before}M")),
+        Description::new(ics::escape_text(description)),
+    )
+}
+
+/// A UTC timestamp as `DTSTAMP` and `LAST-MODIFIED` take it.
+pub fn utc_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
@@ -1,26 +1,42 @@
+mod config;
 mod cookie_refresher;
 mod endpoints;
+mod error;
+mod filters;
+mod ical;
+mod mock;
+mod profiles;
+mod recurrence;
 mod requests;
+mod revisions;
+mod week_plan;
 
-use actix_web::{get, App, HttpServer, Responder};
+#[cfg(test)]
+mod testing;
+
+use actix_web::{get, web, App, HttpResponse, HttpServer};
 use anyhow::{bail, Context, Result};
 use chrono::Datelike;
 use chrono::NaiveDate;
-use chrono::Weekday;
 use chrono::{Local, Timelike, Utc};
 use html_parser::{Dom, Node};
 
+use config::RemindersConfig;
+use error::Error;
+use filters::{Criteria, Filter, List};
+use futures_util::future::try_join_all;
 use hyper::Body;
-use ics::{components::Property, Event, ICalendar};
-use requests::body_text;
-use requests::AuthInfo;
-use requests::Group;
-use requests::GROUP_ONE_AUTH;
-use requests::GROUP_ONE_CACHE;
-use requests::GROUP_TWO_AUTH;
-use requests::GROUP_TWO_CACHE;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, fs};
+use ics::{components::Property, Alarm, Event};
+use profiles::Profile;
+use requests::{AuthInfo, CachedTerm, CachedWeek, CalendarCache};
+use revisions::{Revision, Revisions};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+use week_plan::{Change, LessonStatus, WeekPlan};
 
 #[derive(Deserialize, Debug)]
 struct LessonPlanResponse {
@@ -51,7 +67,9 @@ struct Lesson {
     room: Option<String>,
     index: usize,
     cancelled: bool,
+    /// The substitute teacher.
     replacement: Option<String>,
+    changes: Vec<Change>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -96,14 +114,7 @@ struct TestsDay {
     tests: Vec<String>,
 }
 
-#[derive(Deserialize)]
-#[serde(untagged)]
-enum SomeResponse {
-    Tests(LastTestsResponse),
-    Plan(LessonPlanResponse),
-}
-
-async fn request_with_bypass(url: &str, auth_info: &AuthInfo) -> Result<SomeResponse> {
+async fn request_with_bypass<T: DeserializeOwned>(url: &str, auth_info: &AuthInfo) -> Result<T> {
     let resp = requests::post(
         url,
         auth_info,
@@ -113,29 +124,67 @@ async fn request_with_bypass(url: &str, auth_info: &AuthInfo) -> Result<SomeResp
     )
     .await?;
 
-    let body = body_text(resp.into_body()).await?;
+    requests::read_json(resp)
+        .await
+        .context("Failed to run request")
+}
+
+async fn get_last_tests(profile: &Profile) -> Result<LastTestsResponse> {
+    let auth_info = profile.auth.lock().await;
 
-    serde_json::from_str(&body).context("Failed to run request")
+    request_with_bypass(
+        format!("/{}/Start.mvc/GetLastTests", config::get().symbol).as_str(),
+        &auth_info,
+    )
+    .await
 }
 
-async fn get_tests(group: Group) -> Result<TestsResponse> {
-    let auth_info = match group {
-        Group::One => GROUP_ONE_AUTH.lock(),
-        Group::Two => GROUP_TWO_AUTH.lock(),
-    }
-    .await;
+/// A test listed by `GetLastTests`, like `Matematyka 19.10.2022 Sprawdzian: Funkcje kwadratowe`.
+#[derive(Serialize)]
+struct Test {
+    date: NaiveDate,
+    subject: String,
+    /// `Sprawdzian`, `Kartkówka`, ...
+    kind: String,
+    topic: Option<String>,
+}
 
-    let Ok(SomeResponse::Tests(data)) =
-        request_with_bypass(format!("/{}/Start.mvc/GetLastTests", std::env::var("SYMBOL").unwrap()).as_str(), &auth_info).await else {
-            bail!("Invalid response");
-        };
+fn parse_tests(data: &LastTestsResponse) -> Result<Vec<Test>, Error> {
+    data.data
+        .iter()
+        .flat_map(|data| &data.content)
+        .map(|test| {
+            let invalid =
+                || Error::UpstreamFormat(format!("Test `{}` doesn't contain its date", test.name));
+
+            let (subject, rest) = test
+                .name
+                .split_once(&format!(" {} ", test.url))
+                .ok_or_else(invalid)?;
+            let date = NaiveDate::parse_from_str(&test.url, "%d.%m.%Y").map_err(|_| invalid())?;
+
+            let (kind, topic) = match rest.split_once(':') {
+                Some((kind, topic)) => (kind, Some(topic.trim()).filter(|topic| !topic.is_empty())),
+                None => (rest, None),
+            };
 
-    drop(auth_info);
+            Ok(Test {
+                date,
+                subject: subject.trim().to_owned(),
+                kind: kind.trim().to_owned(),
+                topic: topic.map(str::to_owned),
+            })
+        })
+        .collect()
+}
 
-    let Some(first_data) = data.data.get(0) else {
-        return Ok(
-            TestsResponse::Failure(TestsResponseFailure { message: "You don't have any tests.".to_string() })
-            );
+async fn get_tests(profile: &Profile) -> Result<TestsResponse> {
+    let data = get_last_tests(profile).await?;
+
+    let Some(first_data) = data.data.first() else {
+        return Ok(TestsResponse::Failure(TestsResponseFailure {
+            message: "You don't have any tests.".to_string(),
+        }));
     };
 
     let mut resp = TestsResponseSuccess { days: Vec::new() };
@@ -169,15 +218,11 @@ async fn get_tests(group: Group) -> Result<TestsResponse> {
 
         let split_by = format!(" {} ", test.url);
 
-        let mut split = test.name.split(&split_by);
+        let (class_name, rest) = test.name.split_once(&split_by).ok_or_else(|| {
+            Error::UpstreamFormat(format!("Test `{}` doesn't contain its date", test.name))
+        })?;
 
-        let class_name = split.next().unwrap();
-
-        let rest = split.next().unwrap();
-
-        let mut type_split = rest.split(':');
-
-        let test_type = type_split.next().unwrap();
+        let test_type = rest.split(':').next().unwrap_or(rest);
 
         let final_string = format!("{class_name} - {test_type}");
 
@@ -187,26 +232,45 @@ async fn get_tests(group: Group) -> Result<TestsResponse> {
     Ok(TestsResponse::Success(resp))
 }
 
-async fn get_plan(group: Group) -> Result<PlanResponse> {
-    let auth_info = match group {
-        Group::One => GROUP_ONE_AUTH.lock(),
-        Group::Two => GROUP_TWO_AUTH.lock(),
-    }
-    .await;
+async fn get_plan(profile: &Profile) -> Result<PlanResponse> {
+    let auth_info = profile.auth.lock().await;
 
-    let Ok(SomeResponse::Plan(data)) =
-        request_with_bypass(format!("/{}/Start.mvc/GetKidsLessonPlan", std::env::var("SYMBOL").unwrap()).as_str(), &auth_info).await else {
-            bail!("Invalid response");
-        };
+    let data: LessonPlanResponse = request_with_bypass(
+        format!("/{}/Start.mvc/GetKidsLessonPlan", config::get().symbol).as_str(),
+        &auth_info,
+    )
+    .await?;
 
     drop(auth_info);
 
+    parse_lesson_plan(&data).map_err(|err| Error::UpstreamFormat(format!("{err:#}")).into())
+}
+
+/// The first element of a lesson plan entry, skipping line breaks.
+fn first_element(html: &Dom) -> Result<&html_parser::Element> {
+    html.children
+        .iter()
+        .filter_map(Node::element)
+        .find(|el| el.name != "br")
+        .context("Missing children.")
+}
+
+/// The first child of `element`, if it's text.
+fn first_text<'a>(element: &'a html_parser::Element, what: &str) -> Result<&'a str> {
+    element
+        .children
+        .first()
+        .and_then(Node::text)
+        .with_context(|| format!("{what} didn't have a first text child."))
+}
+
+fn parse_lesson_plan(data: &LessonPlanResponse) -> Result<PlanResponse> {
     let mut resp = PlanResponse {
         header: None,
         lessons: Vec::new(),
     };
 
-    let Some(first_data) = data.data.get(0) else {
+    let Some(first_data) = data.data.first() else {
         resp.header = Some("Brak lekcji.".to_owned());
 
         return Ok(resp);
@@ -216,26 +280,9 @@ async fn get_plan(group: Group) -> Result<PlanResponse> {
 
     while let Some(class) = iter.next() {
         let html = Dom::parse(&class.element)?;
+        let element = first_element(&html)?;
 
-        let element = &html
-            .children
-            .iter()
-            .find(|node| {
-                if let Some(el) = node.element() {
-                    el.name != "br"
-                } else {
-                    false
-                }
-            })
-            .context("Missing children.")?
-            .element()
-            .unwrap();
-
-        let class_list = &element.classes;
-
-        if class_list.contains(&"dayHeader".to_string()) {
-            // It's a day header.
-
+        if element.classes.contains(&"dayHeader".to_string()) {
             if resp.header.is_some() {
                 break;
             }
@@ -245,66 +292,38 @@ async fn get_plan(group: Group) -> Result<PlanResponse> {
             if now.hour() >= 15 && first_data.content.len() > 11 {
                 // Show next day.
 
-                while let Some(class) = iter.next() {
-                    println!("Checking element {}", class.element);
+                for class in iter.by_ref() {
                     let html = Dom::parse(&class.element)?;
+                    let element = first_element(&html)?;
 
-                    let element = &html
-                        .children
-                        .iter()
-                        .find(|node| {
-                            if let Some(el) = node.element() {
-                                el.name != "br"
-                            } else {
-                                false
-                            }
-                        })
-                        .context("Missing children.")?
-                        .element()
-                        .unwrap();
-
-                    let class_list = &element.classes;
-
-                    if class_list.contains(&"dayHeader".to_string()) {
-                        println!("It's a day header");
-                        let text = element.children[0]
-                            .text()
-                            .context("Header didn't have a first text child.")?;
-
-                        resp.header = Some(text.to_owned());
+                    if element.classes.contains(&"dayHeader".to_string()) {
+                        resp.header = Some(first_text(element, "Header")?.to_owned());
                         break;
                     }
                 }
             } else {
-                let text = element.children[0]
-                    .text()
-                    .context("Header didn't have a first text child.")?;
-
-                resp.header = Some(text.to_string());
+                resp.header = Some(first_text(element, "Header")?.to_owned());
             }
         } else {
             // Must be a class!
 
-            let index = &element.children[0]
-                .text()
-                .context("Class element didn't have the index as first value.")?;
+            let index = first_text(element, "Class element")?;
 
             let mut cancelled = false;
             let mut replacement = None;
 
-            println!("{:#?}", html.children);
-
-            let name_and_room = match &html.children[1] {
-                Node::Element(el) => {
+            let name_and_room = match html.children.get(1) {
+                Some(Node::Element(el)) => {
                     if el.classes.contains(&"striked".to_owned()) {
                         cancelled = true;
                     }
-                    el.children[0]
-                        .text()
-                        .context("Failed to get name and room text")?
+                    first_text(el, "Name and room")?
                 }
-                Node::Text(text) => &text[8..],
-                _ => unreachable!(),
+                Some(Node::Text(text)) => text.get(8..).context("Name and room text too short")?,
+                _ => bail!(
+                    "Class element didn't have the name and room: {}",
+                    class.element
+                ),
             };
 
             let mut name_and_room_text = name_and_room.split(", sala ");
@@ -312,28 +331,42 @@ async fn get_plan(group: Group) -> Result<PlanResponse> {
             let annotation_el = html
                 .children
                 .iter()
-                .find(|el| {
-                    if let Some(el) = el.element() {
-                        el.name == "div" && el.classes.contains(&"annotation".to_owned())
-                    } else {
-                        false
-                    }
-                })
-                .map(|el| el.element().unwrap());
-
-            if let Some(annotation_el) = annotation_el {
-                if !cancelled {
-                    for child in &annotation_el.children {
-                        if let Some(text) = child.text() {
-                            replacement = Some(text[14..(text.len() - 1)].to_owned());
-                            break;
-                        }
+                .filter_map(Node::element)
+                .find(|el| el.name == "div" && el.classes.contains(&"annotation".to_owned()));
+
+            let annotation = annotation_el
+                .and_then(|annotation_el| annotation_el.children.iter().find_map(Node::text))
+                .map(|text| {
+                    let text = text.trim();
+                    text.strip_prefix('(')
+                        .and_then(|text| text.strip_suffix(')'))
+                        .unwrap_or(text)
+                        .to_owned()
+                });
+
+            let mut changes = Vec::new();
+
+            match (
+                annotation.as_deref().and_then(week_plan::parse_annotation),
+                cancelled,
+            ) {
+                (Some(moved @ Change::MovedTo { .. }), true) => changes.push(moved),
+                (_, true) => changes.push(Change::Cancelled { reason: annotation }),
+                (Some(change), false) => {
+                    if let Change::TeacherSubstituted { substitute, .. } = &change {
+                        replacement.clone_from(substitute);
                     }
+
+                    changes.push(change);
                 }
+                (None, false) => {}
             }
 
             resp.lessons.push(Lesson {
-                index: index[..(index.len() - 1)].to_owned().parse()?,
+                index: index
+                    .strip_suffix('.')
+                    .context("Lesson index didn't end with a period")?
+                    .parse()?,
                 name: name_and_room_text
                     .next()
                     .context("Name missing")?
@@ -341,6 +374,7 @@ async fn get_plan(group: Group) -> Result<PlanResponse> {
                 room: name_and_room_text.next().map(|v| v.to_owned()),
                 cancelled,
                 replacement,
+                changes,
             })
         }
     }
@@ -348,350 +382,1377 @@ async fn get_plan(group: Group) -> Result<PlanResponse> {
     Ok(resp)
 }
 
-#[get("/g1/plan")]
-async fn plan_1() -> impl Responder {
-    let data = get_plan(Group::One).await;
+#[get("/{profile}/plan")]
+async fn plan(profile: web::Path<String>) -> Result<HttpResponse, Error> {
+    let profile = profiles::find(&profile)?;
 
-    match data {
-        Err(err) => {
-            eprintln!("{:#?}", err);
-            "Failed to get plan".to_owned()
+    Ok(HttpResponse::Ok().json(get_plan(profile).await?))
+}
+
+#[derive(Deserialize)]
+struct WeekQuery {
+    /// Any day of the week, today's by default.
+    date: Option<NaiveDate>,
+}
+
+/// The week plan with every lesson's changes, as parsed for the calendars.
+#[get("/{profile}/week")]
+async fn timetable(
+    profile: web::Path<String>,
+    query: web::Query<WeekQuery>,
+) -> Result<HttpResponse, Error> {
+    let profile = profiles::find(&profile)?;
+
+    let date = query.date.unwrap_or_else(|| Local::now().date_naive());
+
+    let mut cache = profile.cache.lock().await;
+    let week = get_weeks(profile, &mut cache, &[monday(date)]).await?;
+
+    Ok(HttpResponse::Ok().json(&week[0]))
+}
+
+#[get("/{profile}/tests")]
+async fn tests(profile: web::Path<String>) -> Result<HttpResponse, Error> {
+    let profile = profiles::find(&profile)?;
+
+    Ok(HttpResponse::Ok().json(get_tests(profile).await?))
+}
+
+/// Which weeks a calendar feed covers, overriding the `[calendar]` config, and which lessons it shows.
+#[derive(Deserialize)]
+struct CalendarQuery {
+    past_weeks: Option<u32>,
+    future_weeks: Option<u32>,
+    #[serde(default)]
+    include_subject: List,
+    #[serde(default)]
+    exclude_subject: List,
+    #[serde(default)]
+    include_teacher: List,
+    #[serde(default)]
+    exclude_teacher: List,
+    #[serde(default)]
+    include_room: List,
+    #[serde(default)]
+    exclude_room: List,
+    #[serde(default)]
+    only_changed: bool,
+    #[serde(default)]
+    hide_cancelled: bool,
+    /// `false` leaves out the reminders from the `[reminders]` config.
+    reminders: Option<bool>,
+    remind_first_lesson: Option<u32>,
+    remind_changed: Option<u32>,
+    remind_test: Option<String>,
+    /// Overrides `calendar.merge_consecutive`.
+    merge: Option<bool>,
+}
+
+impl CalendarQuery {
+    fn reminders(&self) -> Result<RemindersConfig, Error> {
+        let mut reminders = match self.reminders {
+            Some(false) => RemindersConfig::default(),
+            _ => config::get().reminders.clone(),
+        };
+
+        if let Some(minutes) = self.remind_first_lesson {
+            reminders.first_lesson = Some(minutes);
+        }
+
+        if let Some(minutes) = self.remind_changed {
+            reminders.changed_lesson = Some(minutes);
+        }
+
+        if let Some(time) = &self.remind_test {
+            reminders.test = Some(config::parse_time(time).ok_or_else(|| {
+                Error::BadRequest(format!("`{time}` is not a time like `19:00`"))
+            })?);
         }
-        Ok(data) => serde_json::to_string(&data).unwrap_or("Failed to get plan".to_owned()),
+
+        Ok(reminders)
     }
-}
 
-#[get("/g2/plan")]
-async fn plan_2() -> impl Responder {
-    let data = get_plan(Group::Two).await;
+    fn filter(&self, profile: &Profile) -> Filter {
+        Filter::new(
+            Criteria {
+                subjects: self.include_subject.0.clone(),
+                teachers: self.include_teacher.0.clone(),
+                rooms: self.include_room.0.clone(),
+            },
+            Criteria {
+                subjects: self.exclude_subject.0.clone(),
+                teachers: self.exclude_teacher.0.clone(),
+                rooms: self.exclude_room.0.clone(),
+            },
+            &profile.config.exclude,
+            self.only_changed,
+            self.hide_cancelled,
+        )
+    }
+
+    /// The Mondays of the covered weeks, oldest first.
+    fn mondays(&self) -> Result<Vec<NaiveDate>, Error> {
+        let config = &config::get().calendar;
+
+        let past_weeks = self.past_weeks.unwrap_or(config.past_weeks);
+        let future_weeks = self.future_weeks.unwrap_or(config.future_weeks);
 
-    match data {
-        Err(err) => {
-            eprintln!("{:#?}", err);
-            "Failed to get plan".to_owned()
+        if past_weeks.max(future_weeks) > config.max_weeks {
+            return Err(Error::BadRequest(format!(
+                "at most {} past or future weeks can be requested",
+                config.max_weeks
+            )));
         }
-        Ok(data) => serde_json::to_string(&data).unwrap_or("Failed to get plan".to_owned()),
+
+        let this_monday = monday(Local::now().date_naive());
+
+        Ok((-i64::from(past_weeks)..=i64::from(future_weeks))
+            .map(|offset| this_monday + chrono::Duration::weeks(offset))
+            .collect())
     }
 }
 
-#[get("/g1/tests")]
-async fn tests_1() -> impl Responder {
-    let data = get_tests(Group::One).await;
+/// The Monday of `date`'s week.
+fn monday(date: NaiveDate) -> NaiveDate {
+    date - chrono::Duration::days(date.weekday().num_days_from_monday().into())
+}
 
-    match data {
-        Err(err) => {
-            eprintln!("{:#?}", err);
-            "Failed to get plan".to_owned()
+/// The week plans starting on `mondays`. Those that aren't cached (anymore) are fetched at the same time.
+async fn get_weeks(
+    profile: &Profile,
+    cache: &mut CalendarCache,
+    mondays: &[NaiveDate],
+) -> Result<Vec<WeekPlan>> {
+    cache.weeks.retain(|_, week| week.is_valid());
+
+    let missing = mondays
+        .iter()
+        .filter(|monday| !cache.weeks.contains_key(monday))
+        .copied()
+        .collect::<Vec<_>>();
+
+    if !missing.is_empty() {
+        let auth_info = profile.auth.lock().await;
+
+        let responses = try_join_all(
+            missing
+                .iter()
+                .map(|monday| endpoints::get_week_plan(*monday, &auth_info)),
+        )
+        .await?;
+
+        drop(auth_info);
+
+        for (monday, response) in missing.into_iter().zip(responses) {
+            let week = week_plan::parse(&response.data)
+                .with_context(|| format!("Failed to parse the week of {monday}"))?;
+
+            cache.weeks.insert(
+                monday,
+                CachedWeek {
+                    fetched: Local::now(),
+                    plan: week,
+                },
+            );
         }
-        Ok(data) => serde_json::to_string(&data).unwrap_or("Failed to get tests".to_owned()),
     }
+
+    Ok(mondays
+        .iter()
+        .map(|monday| cache.weeks[monday].plan.clone())
+        .collect())
 }
 
-#[get("/g2/tests")]
-async fn tests_2() -> impl Responder {
-    let data = get_tests(Group::Two).await;
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Feed {
+    /// The timetable without substitutions, cancelled lessons included.
+    Regular,
+    /// Only the substitutions.
+    Replacements,
+    /// What actually takes place: substitutions instead of the lessons they replace.
+    Effective,
+}
 
-    match data {
-        Err(err) => {
-            eprintln!("{:#?}", err);
-            "Failed to get plan".to_owned()
+impl Feed {
+    fn includes(self, lesson: &week_plan::Lesson, week: &WeekPlan) -> bool {
+        match self {
+            Feed::Regular => lesson.status != LessonStatus::Substitution,
+            Feed::Replacements => lesson.status == LessonStatus::Substitution,
+            Feed::Effective => !week
+                .lessons
+                .iter()
+                .any(|other| lesson.is_replaced_by(other)),
         }
-        Ok(data) => serde_json::to_string(&data).unwrap_or("Failed to get tests".to_owned()),
     }
 }
 
-async fn get_calendar(group: Group, replacements: bool) -> Result<String> {
-    let mut cache = match group {
-        Group::One => GROUP_ONE_CACHE.lock(),
-        Group::Two => GROUP_TWO_CACHE.lock(),
+/// The `feed` calendar of the weeks starting on `mondays`.
+async fn get_calendar(
+    profile: &Profile,
+    feed: Feed,
+    query: &CalendarQuery,
+    mondays: &[NaiveDate],
+) -> Result<String> {
+    let filter = query.filter(profile);
+    let reminders = query.reminders()?;
+
+    let mut cache = profile.cache.lock().await;
+
+    let weeks = get_weeks(profile, &mut cache, mondays).await?;
+
+    let mut calendar_feed = ical::new();
+
+    // Entries sharing a cell without telling their groups apart still need distinct UIDs.
+    let mut uses_of_uid = HashMap::new();
+
+    let lessons = weeks
+        .iter()
+        .flat_map(|week| week.lessons.iter().map(move |lesson| (lesson, week)))
+        .filter(|(lesson, week)| feed.includes(lesson, week) && filter.matches(lesson))
+        .map(|(lesson, _)| lesson.clone())
+        .collect::<Vec<_>>();
+
+    let mut first_lessons = HashMap::new();
+
+    for lesson in &lessons {
+        if lesson.status != LessonStatus::Cancelled {
+            let first = first_lessons.entry(lesson.date).or_insert(lesson.start);
+            *first = lesson.start.min(*first);
+        }
     }
-    .await;
 
-    if !cache.is_valid() {
-        let mut regular_calendar = ICalendar::new("2.0", "ics-rs");
-        let mut replacements_calendar = ICalendar::new("2.0", "ics-rs");
+    let merge = query
+        .merge
+        .unwrap_or(config::get().calendar.merge_consecutive);
+
+    for entry in entries(lessons, &weeks, merge) {
+        let first = entry.first();
 
-        async fn parse_week<'a>(
-            weeks_skipped: u32,
-            group: &Group,
-        ) -> Result<(Vec<Event<'a>>, Vec<Event<'a>>)> {
-            let mut regular_events: Vec<Event<'a>> = Vec::new();
-            let mut replacement_events: Vec<Event<'a>> = Vec::new();
+        let uid = unique_uid(&mut uses_of_uid, event_uid(profile, &entry));
+        let revision = cache.revisions.update(&uid, &entry.content()?);
 
-            let now = Local::now();
+        let mut event = lesson_event(uid, &revision, &entry);
 
-            let auth_info = match group {
-                Group::One => GROUP_ONE_AUTH.lock(),
-                Group::Two => GROUP_TWO_AUTH.lock(),
+        if !entry.all_day {
+            let first_of_day = first_lessons.get(&first.date) == Some(&first.start);
+
+            for alarm in lesson_alarms(first, &reminders, first_of_day) {
+                event.add_alarm(alarm);
             }
-            .await;
+        }
 
-            let data = endpoints::get_week_plan(
-                NaiveDate::from_isoywd_opt(
-                    now.iso_week().year(),
-                    now.iso_week().week() + weeks_skipped,
-                    Weekday::Mon,
-                )
-                .context("Failed to create date for monday")?,
-                &auth_info,
-            )
-            .await?;
+        calendar_feed.add_event(event);
+    }
 
-            drop(auth_info);
+    cache.revisions.save();
 
-            for (_, row) in data.data.rows.iter().enumerate() {
-                for (col_index, col) in row.iter().enumerate() {
-                    if col_index == 0 {
-                        continue; // Index 0 is always the lesson hour.
-                    }
+    Ok(calendar_feed.to_string())
+}
 
-                    if col == "" {
-                        continue; // Empty means no lesson.
-                    }
+/// What becomes one calendar event: a lesson, or all lessons of a block subject (`calendar.blocks`) on a day.
+#[derive(Serialize)]
+struct Entry {
+    /// In order, never empty.
+    lessons: Vec<week_plan::Lesson>,
+    /// The lessons make up the whole day.
+    all_day: bool,
+}
 
-                    let hour = row.get(0).unwrap();
+impl Entry {
+    fn first(&self) -> &week_plan::Lesson {
+        &self.lessons[0]
+    }
 
-                    let date_chars = data
-                        .data
-                        .headers
-                        .get(col_index)
-                        .context("No header for current class")?
-                        .text
-                        .chars();
+    fn last(&self) -> &week_plan::Lesson {
+        &self.lessons[self.lessons.len() - 1]
+    }
 
-                    let date = date_chars
-                        .skip_while(|char| char != &'>')
-                        .skip(1)
-                        .collect::<String>();
+    /// What the revision of the entry's event is computed from. A single lesson is hashed on its own, so its
+    /// events keep the sequence numbers they had before lessons were grouped into entries.
+    fn content(&self) -> serde_json::Result<Vec<u8>> {
+        match self.lessons.as_slice() {
+            [lesson] if !self.all_day => serde_json::to_vec(lesson),
+            _ => serde_json::to_vec(self),
+        }
+    }
+}
 
-                    let mut date_elements = date.split(".");
+/// Turns every lesson into an entry, except for the lessons of block subjects, which are joined per day, and
+/// with `merge`, consecutive lessons that only differ in their slot.
+fn entries(lessons: Vec<week_plan::Lesson>, weeks: &[WeekPlan], merge: bool) -> Vec<Entry> {
+    let blocks = &config::get().calendar.blocks;
 
-                    let day = date_elements.next().unwrap();
-                    let month = date_elements.next().unwrap();
-                    let year = date_elements.next().unwrap();
+    let mut entries: Vec<Entry> = Vec::new();
 
-                    let start_hour = format!("{}{}00", &hour[7..9], &hour[10..12]);
-                    let end_hour = format!("{}{}00", &hour[18..20], &hour[21..23]);
+    for lesson in lessons {
+        if !filters::contains(blocks, &lesson.subject) {
+            entries.push(Entry {
+                lessons: vec![lesson],
+                all_day: false,
+            });
 
-                    let cancelled = col.contains("x-treelabel-inv");
-                    let replacement = col.contains("x-treelabel-zas");
+            continue;
+        }
 
-                    let name = col
-                        .chars()
-                        .skip_while(|char| char != &'>')
-                        .skip(1)
-                        .skip_while(|char| char != &'>')
-                        .skip(1)
-                        .take_while(|char| char != &'<')
-                        .collect::<String>();
+        let block = entries.iter_mut().find(|entry| {
+            let first = entry.first();
 
-                    if name == "Praktyka zawodowa" {
-                        continue;
-                    }
+            filters::contains(blocks, &first.subject)
+                && (first.date, &first.subject, first.status)
+                    == (lesson.date, &lesson.subject, lesson.status)
+        });
 
-                    let html = Dom::parse(&col)?;
+        match block {
+            Some(block) => block.lessons.push(lesson),
+            None => entries.push(Entry {
+                lessons: vec![lesson],
+                all_day: false,
+            }),
+        }
+    }
 
-                    let content = html.children[0].element().unwrap();
+    for entry in &mut entries {
+        let first = entry.first();
 
-                    let mut has_empty_el = 0;
-                    let mut room = content.children[1].element().unwrap();
+        if !filters::contains(blocks, &first.subject) {
+            continue;
+        }
 
-                    if room.children.is_empty() {
-                        has_empty_el = 1;
-                        room = content.children[2].element().unwrap();
-                    }
+        entry.all_day = weeks
+            .iter()
+            .flat_map(|week| &week.lessons)
+            .filter(|lesson| lesson.date == first.date)
+            .all(|lesson| lesson.subject == first.subject);
 
-                    let room = room
-                        .children
-                        .get(0)
-                        .map(|room| room.text().map(|text| text.to_string()))
-                        .flatten();
-
-                    let teacher_og = content
-                        .children
-                        .get(2 + has_empty_el)
-                        .map(|teacher| {
-                            teacher.element().map(|el| {
-                                el.children
-                                    .get(0)
-                                    .map(|text| text.text().unwrap().to_string())
-                            })
-                        })
-                        .flatten()
-                        .flatten();
-
-                    let start = format!("{}{}{}T{}", year, month, day, start_hour);
-
-                    let mut event = Event::new(
-                        start.clone(),
-                        Utc::now().format("%Y%m%dT%H%M%S").to_string(),
-                    );
-
-                    if let Some(teacher_og) = teacher_og {
-                        let mut teacher_words = teacher_og.split(" ").collect::<Vec<_>>();
-                        teacher_words.reverse();
-
-                        let teacher = teacher_words.join(" ");
-
-                        event.push(Property::new(
-                            format!("ORGANIZER;CN=\"{}\"", teacher),
-                            format!(
-                                "MAILTO:{}@{}",
-                                unidecode::unidecode(
-                                    teacher.to_lowercase().replace(" ", ".").as_str()
-                                ),
-                                std::env::var("SCHOOL_MAIL").unwrap()
-                            ),
-                        ));
-                    }
+        entry.lessons.sort_by_key(|lesson| lesson.number);
+    }
 
-                    let notes = content.children.last().map(|child| child.text()).flatten();
+    if merge {
+        entries = merge_consecutive(entries);
+    }
 
-                    event.push(Property::new("SUMMARY", name));
-                    event.push(Property::new("DTSTART", start));
-                    event.push(Property::new(
-                        "DTEND",
-                        format!("{}{}{}T{}", year, month, day, end_hour),
-                    ));
+    entries
+}
 
-                    if cancelled {
-                        event.push(Property::new("STATUS", "CANCELLED"));
-                    }
+fn merge_consecutive(mut entries: Vec<Entry>) -> Vec<Entry> {
+    let max_break = chrono::Duration::minutes(config::get().calendar.merge_max_break.into());
+
+    entries.sort_by_key(|entry| (entry.first().date, entry.first().number));
+
+    let mut merged: Vec<Entry> = Vec::new();
+
+    for entry in entries {
+        let next = entry.first();
+
+        // Lessons of different groups run side by side, so the previous lesson isn't necessarily the last entry.
+        let previous = merged.iter_mut().find(|previous| {
+            let last = previous.last();
+
+            !previous.all_day
+                && !entry.all_day
+                && last.date == next.date
+                && last.number + 1 == next.number
+                && next.start - last.end <= max_break
+                && (
+                    &last.subject,
+                    &last.teacher,
+                    &last.room,
+                    &last.group,
+                    last.status,
+                ) == (
+                    &next.subject,
+                    &next.teacher,
+                    &next.room,
+                    &next.group,
+                    next.status,
+                )
+                && last.changes == next.changes
+        });
 
-                    if let Some(room) = room {
-                        event.push(Property::new("LOCATION", room));
-                    }
+        match previous {
+            Some(previous) => previous.lessons.extend(entry.lessons),
+            None => merged.push(entry),
+        }
+    }
 
-                    if let Some(description) = notes {
-                        event.push(Property::new(
-                            "DESCRIPTION",
-                            description[1..description.len() - 1].to_string(),
-                        ));
-                    }
+    merged
+}
 
-                    if replacement {
-                        replacement_events.push(event)
-                    } else {
-                        regular_events.push(event)
-                    }
-                }
-            }
+/// The alarms `reminders` ask for on `lesson`. Cancelled lessons get none.
+fn lesson_alarms<'a>(
+    lesson: &week_plan::Lesson,
+    reminders: &RemindersConfig,
+    first_of_day: bool,
+) -> Vec<Alarm<'a>> {
+    let mut alarms = Vec::new();
+
+    if lesson.status == LessonStatus::Cancelled {
+        return alarms;
+    }
+
+    if let Some(minutes) = reminders.first_lesson.filter(|_| first_of_day) {
+        alarms.push(ical::alarm(
+            minutes,
+            format!("Pierwsza lekcja: {}", lesson.title()),
+        ));
+    }
+
+    if let Some(minutes) = reminders
+        .changed_lesson
+        .filter(|_| !lesson.changes.is_empty())
+    {
+        let changes = lesson
+            .changes
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        alarms.push(ical::alarm(
+            minutes,
+            format!("{}: {}", lesson.title(), changes.join(", ")),
+        ));
+    }
+
+    alarms
+}
 
-            Ok((regular_events, replacement_events))
+async fn get_tests_calendar(profile: &Profile, query: &CalendarQuery) -> Result<String> {
+    let reminders = query.reminders()?;
+    let upcoming = parse_tests(&get_last_tests(profile).await?)?;
+
+    let mut cache = profile.cache.lock().await;
+
+    let mut calendar_feed = ical::new();
+    let mut uses_of_uid = HashMap::new();
+
+    for test in upcoming {
+        let uid = unique_uid(
+            &mut uses_of_uid,
+            format!(
+                "{}-{}-sprawdzian-{}@uonetplan",
+                profile.name(),
+                test.date.format("%Y%m%d"),
+                uid_part(&test.subject)
+            ),
+        );
+        let revision = cache.revisions.update(&uid, &serde_json::to_vec(&test)?);
+
+        let mut event = Event::new(uid, ical::utc_time(Utc::now()));
+        event.push(Property::new("SEQUENCE", revision.sequence.to_string()));
+        event.push(Property::new(
+            "LAST-MODIFIED",
+            ical::utc_time(revision.last_modified),
+        ));
+        event.push(Property::new(
+            "SUMMARY",
+            format!("{}: {}", test.kind, test.subject),
+        ));
+        event.push(ical::date("DTSTART", test.date));
+        event.push(ical::date("DTEND", test.date + chrono::Duration::days(1)));
+        event.push(Property::new("CATEGORIES", test.kind.clone()));
+
+        if let Some(topic) = &test.topic {
+            event.push(Property::new(
+                "DESCRIPTION",
+                ics::escape_text(topic.clone()),
+            ));
         }
 
-        let weeks = vec![
-            parse_week(0, &group).await.unwrap(),
-            parse_week(1, &group).await.unwrap(),
-            parse_week(2, &group).await.unwrap(),
-        ];
+        if let Some(time) = reminders.test {
+            // The event starts at midnight, so the evening before is less than a day earlier.
+            let minutes_before = 24 * 60 - time.num_seconds_from_midnight() / 60;
 
-        for week in weeks {
-            for event in week.0 {
-                regular_calendar.add_event(event);
-            }
-            for event in week.1 {
-                replacements_calendar.add_event(event);
-            }
+            event.add_alarm(ical::alarm(
+                minutes_before,
+                format!("Jutro {}: {}", test.kind.to_lowercase(), test.subject),
+            ));
         }
 
-        {
-            let mut buffer = Vec::new();
-            regular_calendar.write(&mut buffer)?;
-            let string = String::from_utf8(buffer)?;
+        calendar_feed.add_event(event);
+    }
+
+    cache.revisions.save();
+
+    Ok(calendar_feed.to_string())
+}
+
+/// The regular timetable as weekly recurring events up to the end of the school year, with the weeks the lessons
+/// don't take place in excluded and their substitutions and cancellations as overrides of single occurrences.
+async fn get_template_calendar(profile: &Profile, query: &CalendarQuery) -> Result<String> {
+    let mondays = query.mondays()?;
+    let filter = query.filter(profile);
+
+    let mut cache = profile.cache.lock().await;
+
+    let mut weeks = get_weeks(profile, &mut cache, &mondays).await?;
+
+    for week in &mut weeks {
+        week.lessons.retain(|lesson| filter.matches(lesson));
+    }
+
+    let term_end = get_term(profile, &mut cache).await?.map_or(
+        mondays[mondays.len() - 1] + chrono::Duration::days(6),
+        |(_, end)| end,
+    );
+
+    let calendar_feed = template_calendar(profile, &mut cache.revisions, &weeks, term_end)?;
+
+    cache.revisions.save();
+
+    Ok(calendar_feed)
+}
+
+/// Renders the template of `weeks`, repeating the lessons until `term_end`. The series start with their first
+/// occurrence in `weeks`, as nothing is known about the weeks before.
+fn template_calendar(
+    profile: &Profile,
+    revisions: &mut Revisions,
+    weeks: &[WeekPlan],
+    term_end: NaiveDate,
+) -> Result<String> {
+    let template = recurrence::detect(weeks);
+
+    let mut calendar_feed = ical::new();
+    let mut uses_of_uid = HashMap::new();
+
+    for series in template.series {
+        let uid = unique_uid(&mut uses_of_uid, series_uid(profile, &series.lesson));
+        let revision = revisions.update(&uid, &serde_json::to_vec(&series)?);
+
+        let start = series.lesson.start;
+        let first_day = series.lesson.date;
+
+        let entry = Entry {
+            lessons: vec![series.lesson],
+            all_day: false,
+        };
+
+        let mut event = lesson_event(uid.clone(), &revision, &entry);
+        event.push(ical::weekly_until(term_end));
 
-            cache.regular_calendar = Some(string);
+        for day in series.missing.into_iter().filter(|day| *day > first_day) {
+            event.push(ical::local_time("EXDATE", day, start));
         }
 
-        {
-            let mut buffer = Vec::new();
-            replacements_calendar.write(&mut buffer)?;
-            let string = String::from_utf8(buffer)?;
+        calendar_feed.add_event(event);
 
-            cache.replacements_calendar = Some(string);
+        for exception in series.exceptions {
+            let date = exception.date;
+
+            let entry = Entry {
+                lessons: vec![exception],
+                all_day: false,
+            };
+
+            let mut event = lesson_event(uid.clone(), &revision, &entry);
+            event.push(ical::local_time("RECURRENCE-ID", date, start));
+
+            calendar_feed.add_event(event);
         }
+    }
+
+    for lesson in template.single {
+        let entry = Entry {
+            lessons: vec![lesson],
+            all_day: false,
+        };
+
+        let uid = unique_uid(&mut uses_of_uid, event_uid(profile, &entry));
+        let revision = revisions.update(&uid, &entry.content()?);
+
+        calendar_feed.add_event(lesson_event(uid, &revision, &entry));
+    }
+
+    Ok(calendar_feed.to_string())
+}
+
+/// The first and last day of the school year of the profile's diary, if Vulcan reports them. They're fetched
+/// again once the school year is over, or a day later if Vulcan didn't report them.
+async fn get_term(
+    profile: &Profile,
+    cache: &mut CalendarCache,
+) -> Result<Option<(NaiveDate, NaiveDate)>> {
+    if !cache.term.as_ref().is_some_and(CachedTerm::is_valid) {
+        let auth_info = profile.auth.lock().await;
+
+        let diaries = endpoints::get_diaries(&auth_info).await?;
+
+        let dates = diaries
+            .iter()
+            .find(|diary| {
+                (diary.student_id, diary.register_id, diary.school_year)
+                    == (
+                        auth_info.student_id,
+                        auth_info.register_id,
+                        auth_info.school_year,
+                    )
+            })
+            .and_then(|diary| Some((diary.start_date()?, diary.end_date()?)));
 
-        cache.last_updated = Some(Local::now());
+        cache.term = Some(CachedTerm {
+            fetched: Local::now(),
+            dates,
+        });
     }
 
-    if replacements {
-        cache
-            .replacements_calendar
-            .clone()
-            .context("Calendar missing")
+    Ok(cache.term.as_ref().and_then(|term| term.dates))
+}
+
+fn lesson_event<'a>(uid: String, revision: &Revision, entry: &Entry) -> Event<'a> {
+    let lesson = entry.first();
+
+    let mut event = Event::new(uid, ical::utc_time(Utc::now()));
+    event.push(Property::new("SEQUENCE", revision.sequence.to_string()));
+    event.push(Property::new(
+        "LAST-MODIFIED",
+        ical::utc_time(revision.last_modified),
+    ));
+
+    if let Some(teacher_og) = &lesson.teacher {
+        let mut teacher_words = teacher_og.split(' ').collect::<Vec<_>>();
+        teacher_words.reverse();
+
+        let teacher = teacher_words.join(" ");
+
+        event.push(Property::new(
+            format!("ORGANIZER;CN=\"{}\"", teacher),
+            format!(
+                "MAILTO:{}@{}",
+                unidecode::unidecode(teacher.to_lowercase().replace(' ', ".").as_str()),
+                config::get().school_mail
+            ),
+        ));
+    }
+
+    event.push(Property::new("SUMMARY", lesson.title()));
+
+    if entry.all_day {
+        event.push(ical::date("DTSTART", lesson.date));
+        event.push(ical::date("DTEND", lesson.date + chrono::Duration::days(1)));
     } else {
-        cache.regular_calendar.clone().context("Calendar missing")
+        event.push(ical::local_time("DTSTART", lesson.date, lesson.start));
+        event.push(ical::local_time("DTEND", lesson.date, entry.last().end));
     }
+
+    if lesson.status == LessonStatus::Cancelled {
+        event.push(Property::new("STATUS", "CANCELLED"));
+    }
+
+    let mut categories = Vec::new();
+
+    for category in entry
+        .lessons
+        .iter()
+        .flat_map(|lesson| &lesson.changes)
+        .map(Change::category)
+    {
+        if !categories.contains(&category) {
+            categories.push(category);
+        }
+    }
+
+    if !categories.is_empty() {
+        event.push(Property::new("CATEGORIES", categories.join(",")));
+    }
+
+    let mut description = Vec::new();
+
+    if entry.lessons.len() > 1 {
+        description.push(format!("Lekcje {}–{}", lesson.number, entry.last().number));
+    }
+
+    description.extend(lesson.description());
+
+    if !description.is_empty() {
+        event.push(Property::new(
+            "DESCRIPTION",
+            ics::escape_text(description.join("\n")),
+        ));
+    }
+
+    if let Some(room) = &lesson.room {
+        event.push(Property::new("LOCATION", room.clone()));
+    }
+
+    event
 }
 
-#[get("/g1/plan_zastepstwa.ics")]
-async fn calendar_replacements_1() -> impl Responder {
-    match get_calendar(Group::One, true).await {
-        Err(_) => "An unknown error occurred.".to_owned(),
-        Ok(value) => value,
+/// A UID that stays the same across refreshes, made of the profile, the lesson's date, number (or first and
+/// last number) and group, and whether it's a substitution, as those share the slot with the lesson they replace.
+fn event_uid(profile: &Profile, entry: &Entry) -> String {
+    let lesson = entry.first();
+
+    let mut uid = format!(
+        "{}-{}-{}",
+        profile.name(),
+        lesson.date.format("%Y%m%d"),
+        lesson.number
+    );
+
+    if entry.lessons.len() > 1 {
+        uid.push_str(&format!("+{}", entry.last().number));
+    }
+
+    if let Some(group) = &lesson.group {
+        uid.push('-');
+        uid.extend(
+            group
+                .chars()
+                .map(|char| if char.is_alphanumeric() { char } else { '_' }),
+        );
     }
+
+    if lesson.status == LessonStatus::Substitution {
+        uid.push_str("-zastepstwo");
+    }
+
+    uid + "@uonetplan"
 }
 
-#[get("/g1/plan.ics")]
-async fn calendar_1() -> impl Responder {
-    match get_calendar(Group::One, false).await {
-        Err(_) => "An unknown error occurred.".to_owned(),
-        Ok(value) => value,
+/// The UID of a recurring lesson, made of the profile, its weekday, number and group, and its subject, which tells
+/// apart a lesson that moved to the slot from the one that was there before.
+fn series_uid(profile: &Profile, lesson: &week_plan::Lesson) -> String {
+    let mut uid = format!(
+        "{}-szablon-{}-{}",
+        profile.name(),
+        lesson.date.format("%a").to_string().to_lowercase(),
+        lesson.number
+    );
+
+    if let Some(group) = &lesson.group {
+        uid.push('-');
+        uid.push_str(&uid_part(group));
     }
+
+    format!("{uid}-{}@uonetplan", uid_part(&lesson.subject))
 }
 
-#[get("/g2/plan_zastepstwa.ics")]
-async fn calendar_replacements_2() -> impl Responder {
-    match get_calendar(Group::Two, true).await {
-        Err(_) => "An unknown error occurred.".to_owned(),
-        Ok(value) => value,
+/// `text` with everything but ASCII letters and digits replaced, for use in UIDs. Lesson UIDs keep other letters
+/// in their groups, as they always did, so calendar apps don't see their events replaced by new ones.
+fn uid_part(text: &str) -> String {
+    unidecode::unidecode(text)
+        .chars()
+        .map(|char| {
+            if char.is_ascii_alphanumeric() {
+                char
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Makes `uid` distinct from the ones already used in a calendar by numbering repeats, for entries that can't
+/// be told apart otherwise.
+fn unique_uid(uses: &mut HashMap<String, u32>, uid: String) -> String {
+    let count = uses.entry(uid.clone()).or_insert(0);
+    *count += 1;
+
+    match *count {
+        1 => uid,
+        count => uid.replacen('@', &format!("-{}@", count - 1), 1),
     }
 }
 
-#[get("/g2/plan.ics")]
-async fn calendar_2() -> impl Responder {
-    match get_calendar(Group::Two, false).await {
-        Err(_) => "An unknown error occurred.".to_owned(),
-        Ok(value) => value,
+#[get("/{profile}/sprawdziany.ics")]
+async fn calendar_tests(
+    profile: web::Path<String>,
+    query: web::Query<CalendarQuery>,
+) -> Result<HttpResponse, Error> {
+    let profile = profiles::find(&profile)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(get_tests_calendar(profile, &query).await?))
+}
+
+#[get("/{profile}/plan_zastepstwa.ics")]
+async fn calendar_replacements(
+    profile: web::Path<String>,
+    query: web::Query<CalendarQuery>,
+) -> Result<HttpResponse, Error> {
+    let profile = profiles::find(&profile)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(get_calendar(profile, Feed::Replacements, &query, &query.mondays()?).await?))
+}
+
+#[get("/{profile}/plan.ics")]
+async fn calendar(
+    profile: web::Path<String>,
+    query: web::Query<CalendarQuery>,
+) -> Result<HttpResponse, Error> {
+    let profile = profiles::find(&profile)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(get_calendar(profile, Feed::Regular, &query, &query.mondays()?).await?))
+}
+
+#[get("/{profile}/plan_aktualny.ics")]
+async fn calendar_effective(
+    profile: web::Path<String>,
+    query: web::Query<CalendarQuery>,
+) -> Result<HttpResponse, Error> {
+    let profile = profiles::find(&profile)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(get_calendar(profile, Feed::Effective, &query, &query.mondays()?).await?))
+}
+
+#[get("/{profile}/plan_szablon.ics")]
+async fn calendar_template(
+    profile: web::Path<String>,
+    query: web::Query<CalendarQuery>,
+) -> Result<HttpResponse, Error> {
+    let profile = profiles::find(&profile)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(get_template_calendar(profile, &query).await?))
+}
+
+#[get("/{profile}/session")]
+async fn session(profile: web::Path<String>) -> Result<HttpResponse, Error> {
+    let profile = profiles::find(&profile)?;
+
+    let status = profile.session.lock().await;
+
+    Ok(HttpResponse::Ok().json(&*status))
+}
+
+/// Service-wide state: the circuit breakers of the upstream hosts.
+#[get("/status")]
+async fn service_status() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "upstream": requests::circuit_breaker::snapshot() }))
+}
+
+#[derive(Serialize)]
+struct StudentsEntry {
+    #[serde(flatten)]
+    diary: endpoints::Diary,
+    class: Option<String>,
+    current: bool,
+    selected: bool,
+}
+
+async fn get_students(profile: &Profile) -> Result<Vec<StudentsEntry>> {
+    let auth_info = profile.auth.lock().await;
+
+    let diaries = endpoints::get_diaries(&auth_info).await?;
+    let selected = (
+        auth_info.student_id,
+        auth_info.register_id,
+        auth_info.school_year,
+    );
+
+    drop(auth_info);
+
+    let today = Local::now().date_naive();
+
+    Ok(diaries
+        .into_iter()
+        .map(|diary| StudentsEntry {
+            class: diary.class_name(),
+            current: diary.contains(today),
+            selected: (diary.student_id, diary.register_id, diary.school_year) == selected,
+            diary,
+        })
+        .collect())
+}
+
+#[get("/{profile}/students")]
+async fn students(profile: web::Path<String>) -> Result<HttpResponse, Error> {
+    let profile = profiles::find(&profile)?;
+
+    Ok(HttpResponse::Ok().json(get_students(profile).await?))
+}
+
+enum Command {
+    /// Serves the calendars, with the config read from this path.
+    Serve(PathBuf),
+    /// Runs the mock Vulcan server instead.
+    Mock(mock::Options),
+}
+
+/// Parses `[--config <path>]` (or `-c <path>`), falling back to the default config path, or `mock [options]`.
+fn parse_args() -> Result<Command> {
+    let mut args = std::env::args().skip(1).peekable();
+
+    if args.peek().is_some_and(|arg| arg == "mock") {
+        return Ok(Command::Mock(mock::Options::parse(args.skip(1))?));
     }
+
+    let mut path = PathBuf::from(config::DEFAULT_CONFIG_PATH);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--config" => {
+                path = args
+                    .next()
+                    .context("--config requires a path argument")?
+                    .into();
+            }
+            _ => bail!("Unknown argument: {arg}"),
+        }
+    }
+
+    Ok(Command::Serve(path))
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    {
-        let mut auth = GROUP_ONE_AUTH.lock().await;
-        auth.cookie = fs::read_to_string("/etc/uonetplan/cookie_1")?
-            .lines()
-            .next()
-            .context("cookie file is empty")?
-            .to_owned();
-    }
+    let config_path = match parse_args()? {
+        Command::Serve(path) => path,
+        Command::Mock(options) => return mock::run(options).await,
+    };
 
-    {
-        let mut auth = GROUP_TWO_AUTH.lock().await;
-        auth.cookie = fs::read_to_string("/etc/uonetplan/cookie_2")?
-            .lines()
-            .next()
-            .context("cookie file is empty")?
-            .to_owned();
-    }
+    let config = config::Config::load(&config_path)?;
+
+    let bind = config.bind;
+
+    config::init(config);
+
+    requests::transport::init()?;
+
+    profiles::init().await?;
 
     let server_task = HttpServer::new(|| {
         App::new()
-            .service(plan_1)
-            .service(plan_2)
-            .service(tests_1)
-            .service(tests_2)
-            .service(calendar_1)
-            .service(calendar_replacements_1)
-            .service(calendar_2)
-            .service(calendar_replacements_2)
+            .app_data(
+                web::PathConfig::default()
+                    .error_handler(|err, _| Error::BadRequest(err.to_string()).into()),
+            )
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|err, _| Error::BadRequest(err.to_string()).into()),
+            )
+            .service(plan)
+            .service(timetable)
+            .service(tests)
+            .service(calendar)
+            .service(calendar_replacements)
+            .service(calendar_effective)
+            .service(calendar_template)
+            .service(calendar_tests)
+            .service(session)
+            .service(students)
+            .service(service_status)
     })
     .disable_signals()
-    .bind(("127.0.0.1", 8080))?
+    .bind(bind)?
     .run();
 
     let (_, _) = tokio::join!(cookie_refresher::spawn_refresher(), server_task);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod feed_tests {
+    use super::*;
+
+    /// The properties of every event in `ics`, in order.
+    fn event_list(ics: &str) -> Vec<Vec<String>> {
+        let mut events = Vec::new();
+        let mut properties = Vec::new();
+
+        for line in ics.lines() {
+            match line {
+                "BEGIN:VEVENT" => properties.clear(),
+                "END:VEVENT" => events.push(properties.clone()),
+                _ => properties.push(line.to_owned()),
+            }
+        }
+
+        events
+    }
+
+    fn uid(event: &[String]) -> &str {
+        event
+            .iter()
+            .find_map(|property| property.strip_prefix("UID:"))
+            .expect("event without a UID")
+    }
+
+    /// The properties of every event in `ics`, by UID.
+    fn parse_events(ics: &str) -> HashMap<String, Vec<String>> {
+        let mut events = HashMap::new();
+
+        for event in event_list(ics) {
+            let uid = uid(&event).to_owned();
+
+            assert!(
+                events.insert(uid.clone(), event).is_none(),
+                "UID {uid} used twice"
+            );
+        }
+
+        events
+    }
+
+    /// The Monday of a week in the fixtures, which cover the weeks of 12.10.2026, 19.10.2026 and 26.10.2026.
+    fn week(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, day).unwrap()
+    }
+
+    /// The `feed` calendar of the `mondays` weeks with the URL query `query`.
+    async fn render_weeks(
+        feed: Feed,
+        query: &str,
+        mondays: &[NaiveDate],
+    ) -> (String, HashMap<String, Vec<String>>) {
+        testing::init();
+
+        let profile = profiles::find("g1").unwrap();
+        let query = serde_urlencoded::from_str::<CalendarQuery>(query).unwrap();
+
+        let ics = get_calendar(profile, feed, &query, mondays).await.unwrap();
+        let events = parse_events(&ics);
+
+        (ics, events)
+    }
+
+    async fn render(feed: Feed) -> (String, HashMap<String, Vec<String>>) {
+        render_weeks(feed, "", &[week(12)]).await
+    }
+
+    fn has(events: &HashMap<String, Vec<String>>, uid: &str, property: &str) -> bool {
+        events
+            .get(uid)
+            .unwrap_or_else(|| panic!("no event {uid}"))
+            .iter()
+            .any(|line| line == property)
+    }
+
+    #[tokio::test]
+    async fn renders_the_regular_timetable() {
+        let (ics, events) = render(Feed::Regular).await;
+
+        assert!(ics.contains("BEGIN:VTIMEZONE\r\nTZID:Europe/Warsaw"));
+        assert_eq!(events.len(), 25);
+        assert!(events
+            .values()
+            .all(|event| event.contains(&"SEQUENCE:0".to_owned())));
+
+        assert!(has(
+            &events,
+            "g1-20261013-1@uonetplan",
+            "DTSTART;TZID=Europe/Warsaw:20261013T080000"
+        ));
+        assert!(events.contains_key("g1-20261012-3-1_2@uonetplan"));
+        assert!(events.contains_key("g1-20261012-3-2_2@uonetplan"));
+
+        assert!(has(&events, "g1-20261012-1@uonetplan", "STATUS:CANCELLED"));
+        assert!(has(
+            &events,
+            "g1-20261012-1@uonetplan",
+            "CATEGORIES:Przeniesiona"
+        ));
+        assert!(has(
+            &events,
+            "g1-20261013-3@uonetplan",
+            "CATEGORIES:Odwołana"
+        ));
+        assert!(!events.keys().any(|uid| uid.contains("zastepstwo")));
+    }
+
+    fn lesson(number: u32) -> week_plan::Lesson {
+        testing::lesson(testing::date(10, 12), number, "Język angielski")
+    }
+
+    #[test]
+    fn keeps_non_ascii_letters_of_groups_in_lesson_uids() {
+        testing::init();
+
+        let mut lesson = lesson(3);
+        lesson.group = Some("gr. Ż/2".to_owned());
+
+        let profile = profiles::find("g1").unwrap();
+
+        assert_eq!(
+            series_uid(profile, &lesson),
+            "g1-szablon-mon-3-gr__Z_2-Jezyk_angielski@uonetplan"
+        );
+
+        let entry = Entry {
+            lessons: vec![lesson],
+            all_day: false,
+        };
+
+        assert_eq!(
+            event_uid(profile, &entry),
+            "g1-20261012-3-gr__Ż_2@uonetplan"
+        );
+    }
+
+    #[test]
+    fn hashes_single_lessons_like_before_they_were_grouped() {
+        let single = Entry {
+            lessons: vec![lesson(1)],
+            all_day: false,
+        };
+
+        assert_eq!(
+            single.content().unwrap(),
+            serde_json::to_vec(&single.lessons[0]).unwrap()
+        );
+
+        let block = Entry {
+            lessons: vec![lesson(1), lesson(2)],
+            all_day: false,
+        };
+
+        assert_eq!(
+            block.content().unwrap(),
+            serde_json::to_vec(&block).unwrap()
+        );
+
+        let all_day = Entry {
+            lessons: vec![lesson(1)],
+            all_day: true,
+        };
+
+        assert_ne!(all_day.content().unwrap(), single.content().unwrap());
+    }
+
+    #[tokio::test]
+    async fn replays_each_week_from_its_own_recording() {
+        let (_, events) = render_weeks(Feed::Regular, "", &[week(12), week(19), week(26)]).await;
+
+        assert_eq!(events.len(), 75);
+
+        for day in [12, 19, 26] {
+            assert!(events.contains_key(&format!("g1-202610{day}-1@uonetplan")));
+        }
+    }
+
+    #[test]
+    fn renders_the_template_as_recurring_events_with_overrides() {
+        testing::init();
+
+        let profile = profiles::find("g1").unwrap();
+        let ics = template_calendar(
+            profile,
+            &mut Revisions::default(),
+            &testing::template_weeks(),
+            NaiveDate::from_ymd_opt(2027, 6, 25).unwrap(),
+        )
+        .unwrap();
+
+        let events = event_list(&ics);
+        let of = |uid_prefix: &str| {
+            events
+                .iter()
+                .filter(|event| uid(event).starts_with(uid_prefix))
+                .collect::<Vec<_>>()
+        };
+        let has = |event: &Vec<String>, property: &str| event.iter().any(|line| line == property);
+
+        // The master events start with their first occurrence in the fetched weeks, not the term.
+        let mathematics = of("g1-szablon-mon-1-");
+        assert_eq!(mathematics.len(), 1);
+        assert!(has(
+            mathematics[0],
+            "DTSTART;TZID=Europe/Warsaw:20261005T080000"
+        ));
+        assert!(has(
+            mathematics[0],
+            "RRULE:FREQ=WEEKLY;UNTIL=20270625T215959Z"
+        ));
+        assert!(has(
+            mathematics[0],
+            "EXDATE;TZID=Europe/Warsaw:20261012T080000"
+        ));
+
+        let physics = of("g1-szablon-mon-2-");
+        assert_eq!(physics.len(), 2);
+        assert!(!physics[0].iter().any(|line| line.starts_with("EXDATE")));
+        assert!(has(
+            physics[1],
+            "RECURRENCE-ID;TZID=Europe/Warsaw:20261012T085500"
+        ));
+        assert!(has(physics[1], "STATUS:CANCELLED"));
+
+        let chemistry = of("g1-szablon-mon-3-");
+        assert_eq!(chemistry.len(), 2);
+        assert!(has(
+            chemistry[1],
+            "RECURRENCE-ID;TZID=Europe/Warsaw:20261019T095000"
+        ));
+        assert!(has(chemistry[1], "SUMMARY:Biologia"));
+        assert!(!has(chemistry[1], "STATUS:CANCELLED"));
+
+        // PE replaces both groups, but is shown once, with German excluded that day.
+        let english = of("g1-szablon-mon-4-1_2-");
+        assert_eq!(english.len(), 2);
+        assert!(has(english[1], "SUMMARY:Wychowanie fizyczne"));
+
+        let german = of("g1-szablon-mon-4-2_2-");
+        assert_eq!(german.len(), 1);
+        assert!(has(german[0], "EXDATE;TZID=Europe/Warsaw:20261012T104500"));
+
+        let single = of("g1-20261005-6");
+        assert_eq!(single.len(), 1);
+        assert!(!single[0].iter().any(|line| line.starts_with("RRULE")));
+
+        assert_eq!(events.len(), 9);
+    }
+
+    #[tokio::test]
+    async fn merges_consecutive_lessons() {
+        let (_, events) = render_weeks(Feed::Regular, "merge=true", &[week(12)]).await;
+
+        // A double period.
+        let biology = &events["g1-20261014-1+2@uonetplan"];
+        assert!(biology.contains(&"DTSTART;TZID=Europe/Warsaw:20261014T080000".to_owned()));
+        assert!(biology.contains(&"DTEND;TZID=Europe/Warsaw:20261014T093500".to_owned()));
+        assert!(biology.contains(&"DESCRIPTION:Lekcje 1–2".to_owned()));
+
+        // The groups run side by side, so each one's lessons are merged past the other's.
+        assert!(events.contains_key("g1-20261012-3+4-1_2@uonetplan"));
+        assert!(events.contains_key("g1-20261012-3+4-2_2@uonetplan"));
+
+        // A lesson moved away isn't merged with the regular one after it.
+        assert!(events.contains_key("g1-20261012-1@uonetplan"));
+        assert!(events.contains_key("g1-20261012-2@uonetplan"));
+
+        assert!(!events.keys().any(|uid| uid.contains("20261014-1@")));
+    }
+
+    #[test]
+    fn leaves_lessons_after_a_long_break_apart() {
+        testing::init();
+
+        let day = testing::date(10, 14);
+        let first = testing::lesson(day, 1, "Biologia");
+
+        let mut after_break = testing::lesson(day, 2, "Biologia");
+        after_break.start = first.end + chrono::Duration::minutes(15);
+
+        let merged = entries(vec![first.clone(), after_break.clone()], &[], true);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].last().number, 2);
+
+        after_break.start = first.end + chrono::Duration::minutes(16);
+
+        let apart = entries(vec![first, after_break], &[], true);
+        assert_eq!(apart.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn renders_only_the_replacements() {
+        let (ics, events) = render(Feed::Replacements).await;
+
+        assert!(ics.contains("BEGIN:VTIMEZONE"));
+
+        let mut uids = events.keys().map(String::as_str).collect::<Vec<_>>();
+        uids.sort();
+
+        assert_eq!(
+            uids,
+            [
+                "g1-20261012-5-zastepstwo@uonetplan",
+                "g1-20261013-3-zastepstwo@uonetplan",
+                "g1-20261013-6-zastepstwo@uonetplan",
+                "g1-20261015-6-zastepstwo@uonetplan",
+            ]
+        );
+        assert!(has(
+            &events,
+            "g1-20261013-3-zastepstwo@uonetplan",
+            "CATEGORIES:Zastępstwo,Zmiana sali"
+        ));
+        assert!(has(
+            &events,
+            "g1-20261013-6-zastepstwo@uonetplan",
+            "CATEGORIES:Dodatkowa lekcja"
+        ));
+        assert!(has(
+            &events,
+            "g1-20261015-6-zastepstwo@uonetplan",
+            "CATEGORIES:Przeniesiona"
+        ));
+        assert!(has(
+            &events,
+            "g1-20261012-5-zastepstwo@uonetplan",
+            "CATEGORIES:Zmiana sali"
+        ));
+        assert!(events
+            .values()
+            .all(|event| event.contains(&"SEQUENCE:0".to_owned())));
+    }
+
+    #[tokio::test]
+    async fn renders_substitutions_instead_of_the_lessons_they_replace() {
+        let (ics, events) = render(Feed::Effective).await;
+
+        assert!(ics.contains("BEGIN:VTIMEZONE"));
+
+        // Replaced by chemistry.
+        assert!(!events.contains_key("g1-20261013-3@uonetplan"));
+        assert!(events.contains_key("g1-20261013-3-zastepstwo@uonetplan"));
+
+        // Cancelled without a substitution.
+        assert!(has(&events, "g1-20261013-2@uonetplan", "STATUS:CANCELLED"));
+        assert!(has(
+            &events,
+            "g1-20261013-2@uonetplan",
+            "CATEGORIES:Odwołana"
+        ));
+
+        assert!(events
+            .values()
+            .all(|event| event.contains(&"SEQUENCE:0".to_owned())));
+    }
+
+    fn lesson_plan(entries: &[&str]) -> LessonPlanResponse {
+        LessonPlanResponse {
+            data: vec![LessonPlanData {
+                content: entries
+                    .iter()
+                    .map(|element| LessonContent {
+                        element: (*element).to_owned(),
+                    })
+                    .collect(),
+            }],
+        }
+    }
+
+    #[test]
+    fn parses_the_lesson_plan_of_the_day() {
+        let day = parse_lesson_plan(&lesson_plan(&[
+            r#"<div class="dayHeader">Dzisiaj, 12.10.2026</div>"#,
+            r#"<div>1.</div><span class="">Matematyka, sala 12</span>"#,
+            r#"<div>2.</div><span class="striked">Fizyka, sala 7</span><div class="annotation">(odwołana)</div>"#,
+            r#"<div class="dayHeader">Jutro, 13.10.2026</div>"#,
+            r#"<div>1.</div><span class="">Język polski, sala 8</span>"#,
+        ]))
+        .unwrap();
+
+        assert_eq!(day.header.as_deref(), Some("Dzisiaj, 12.10.2026"));
+        assert_eq!(day.lessons.len(), 2);
+        assert_eq!(day.lessons[0].index, 1);
+        assert_eq!(day.lessons[0].name, "Matematyka");
+        assert_eq!(day.lessons[0].room.as_deref(), Some("12"));
+        assert!(day.lessons[1].cancelled);
+    }
+
+    #[test]
+    fn rejects_malformed_lesson_plan_entries() {
+        for entry in [
+            r#"<div class="dayHeader"></div>"#,
+            "<div></div>",
+            "<div>1.</div>",
+            r#"<div>1</div><span class="">Matematyka, sala 12</span>"#,
+            r#"<div>1.</div><span class=""></span>"#,
+        ] {
+            assert!(
+                parse_lesson_plan(&lesson_plan(&[entry])).is_err(),
+                "accepted {entry}"
+            );
+        }
+    }
+}
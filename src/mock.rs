@@ -0,0 +1,573 @@
+//! A stand-in for Vulcan that serves the endpoints uonetplan uses from fixture files, so the routes, the session
+//! refresher and the calendars can be tried end to end without outside services. Started with `uonetplan mock`
+//! and used by pointing `upstream.base_url` at it.
+//!
+//! Sessions behave like Vulcan's: every `RefreshSession` rotates the `EfebSsoCookie` and invalidates the previous
+//! one, a cookie that isn't refreshed within the session lifetime expires, and requests with an invalid cookie
+//! are redirected to the login page. `POST /mock/expire` expires every session at once, and `GET /mock/session`
+//! prints a new cookie, e.g. for a profile's `recovery_command`.
+//!
+//! Logging in works like on Vulcan too: `LoginEndpoint.aspx` redirects to the CUFS sign-in URL and on to the login
+//! form, which accepts the `--login` and `--password` given to the mock and answers with the WS-Federation form
+//! that is posted back to `LoginEndpoint.aspx` for the cookie. Wrong credentials show the form again with an error.
+//!
+//! The fixtures are bundled into the binary; `--fixtures <dir>` overrides them with the files found there. In the
+//! fixtures, `{{date+N}}` is replaced with the date N days later as `dd.mm.yyyy` and `{{iso+N}}` as `yyyy-mm-dd`,
+//! counting from the requested week's Monday in `week_plan.json` and from today everywhere else.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{get, http::header, post, web, App, HttpRequest, HttpResponse, HttpServer};
+use anyhow::{bail, Context, Result};
+use chrono::{Local, NaiveDate};
+use serde::Deserialize;
+
+const BUNDLED_FIXTURES: [(&str, &str); 5] = [
+    ("start.html", include_str!("../fixtures/start.html")),
+    (
+        "lesson_plan.json",
+        include_str!("../fixtures/lesson_plan.json"),
+    ),
+    (
+        "last_tests.json",
+        include_str!("../fixtures/last_tests.json"),
+    ),
+    ("week_plan.json", include_str!("../fixtures/week_plan.json")),
+    ("diaries.json", include_str!("../fixtures/diaries.json")),
+];
+
+pub struct Options {
+    pub bind: SocketAddr,
+    pub fixtures: Option<PathBuf>,
+    /// How long a cookie stays valid without being refreshed.
+    pub session_lifetime: Duration,
+    /// The cookie that's valid at startup.
+    pub cookie: String,
+    /// The credentials the login form accepts.
+    pub login: String,
+    pub password: String,
+}
+
+impl Options {
+    /// Parses the arguments following `mock`.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut options = Self {
+            bind: SocketAddr::from(([127, 0, 0, 1], 9000)),
+            fixtures: None,
+            session_lifetime: Duration::from_secs(20 * 60),
+            cookie: "mock-session".to_owned(),
+            login: "jan.kowalski".to_owned(),
+            password: "haslo".to_owned(),
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("{arg} requires an argument"))
+            };
+
+            match arg.as_str() {
+                "--bind" => options.bind = value()?.parse().context("Invalid --bind address")?,
+                "--fixtures" => options.fixtures = Some(value()?.into()),
+                "--session-lifetime" => {
+                    options.session_lifetime = Duration::from_secs(
+                        value()?
+                            .parse()
+                            .context("--session-lifetime takes a number of seconds")?,
+                    )
+                }
+                "--cookie" => options.cookie = value()?,
+                "--login" => options.login = value()?,
+                "--password" => options.password = value()?,
+                _ => bail!("Unknown argument: {arg}"),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+struct Fixtures(HashMap<&'static str, String>);
+
+impl Fixtures {
+    fn load(dir: Option<&Path>) -> Result<Self> {
+        let mut fixtures = HashMap::new();
+
+        for (name, bundled) in BUNDLED_FIXTURES {
+            let path = dir.map(|dir| dir.join(name));
+
+            let fixture = match path.filter(|path| path.exists()) {
+                Some(path) => fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+                None => bundled.to_owned(),
+            };
+
+            fixtures.insert(name, fixture);
+        }
+
+        Ok(Self(fixtures))
+    }
+
+    fn render(&self, name: &str, base: NaiveDate) -> String {
+        render(
+            self.0.get(name).map_or("", |fixture| fixture.as_str()),
+            base,
+        )
+    }
+}
+
+/// Replaces the `{{date+N}}` and `{{iso+N}}` placeholders, counting days from `base`.
+fn render(template: &str, base: NaiveDate) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find("}}") else {
+            break;
+        };
+
+        let placeholder = &rest[2..end];
+
+        let date = |offset: &str| {
+            let days = match offset {
+                "" => 0,
+                _ => offset.strip_prefix('+').unwrap_or(offset).parse().ok()?,
+            };
+
+            base.checked_add_signed(chrono::Duration::days(days))
+        };
+
+        let value = if let Some(offset) = placeholder.strip_prefix("date") {
+            date(offset).map(|date| date.format("%d.%m.%Y").to_string())
+        } else if let Some(offset) = placeholder.strip_prefix("iso") {
+            date(offset).map(|date| date.format("%Y-%m-%d").to_string())
+        } else {
+            None
+        };
+
+        match value {
+            Some(value) => rendered.push_str(&value),
+            None => rendered.push_str(&rest[..end + 2]),
+        }
+
+        rest = &rest[end + 2..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+struct Sessions {
+    /// Valid cookies and when they expire.
+    valid: HashMap<String, Instant>,
+    minted: u32,
+    lifetime: Duration,
+    /// Sign-in tokens handed out by the login form and not yet exchanged for a cookie.
+    tokens: HashSet<String>,
+}
+
+impl Sessions {
+    fn is_valid(&mut self, cookie: &str) -> bool {
+        let now = Instant::now();
+
+        self.valid.retain(|cookie, expires| {
+            let valid = *expires > now;
+
+            if !valid {
+                println!("Session {cookie} expired.");
+            }
+
+            valid
+        });
+
+        self.valid.contains_key(cookie)
+    }
+
+    fn mint(&mut self) -> String {
+        self.minted += 1;
+
+        let cookie = format!("mock-session-{}", self.minted);
+        self.valid
+            .insert(cookie.clone(), Instant::now() + self.lifetime);
+
+        cookie
+    }
+
+    fn issue_token(&mut self) -> String {
+        self.minted += 1;
+
+        let token = format!("mock-token-{}", self.minted);
+        self.tokens.insert(token.clone());
+
+        token
+    }
+
+    /// Replaces `cookie` with a new one, or returns `None` if it isn't valid (anymore).
+    fn rotate(&mut self, cookie: &str) -> Option<String> {
+        if !self.is_valid(cookie) {
+            return None;
+        }
+
+        self.valid.remove(cookie);
+
+        let new_cookie = self.mint();
+        println!("Session {cookie} rotated to {new_cookie}.");
+
+        Some(new_cookie)
+    }
+}
+
+struct MockState {
+    fixtures: Fixtures,
+    sessions: Mutex<Sessions>,
+    login: String,
+    password: String,
+}
+
+impl MockState {
+    fn sessions(&self) -> std::sync::MutexGuard<'_, Sessions> {
+        self.sessions.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Whether the request carries a valid `EfebSsoCookie`.
+    fn authorized(&self, req: &HttpRequest) -> bool {
+        session_cookie(req).is_some_and(|cookie| self.sessions().is_valid(&cookie))
+    }
+}
+
+fn session_cookie(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get_all(header::COOKIE)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| cookie::Cookie::split_parse(value).flatten())
+        .find(|cookie| cookie.name() == "EfebSsoCookie")
+        .map(|cookie| cookie.value().to_owned())
+}
+
+/// What Vulcan answers requests without a valid session with.
+fn login_redirect(symbol: &str) -> HttpResponse {
+    HttpResponse::Found()
+        .insert_header((
+            header::LOCATION,
+            format!("https://uonetplus.vulcan.net.pl/{symbol}/LoginEndpoint.aspx"),
+        ))
+        .finish()
+}
+
+fn html(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body)
+}
+
+fn json(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .body(body)
+}
+
+fn today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+#[get("/{symbol}/{school_id}/Start")]
+async fn student_panel(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    state: web::Data<MockState>,
+) -> HttpResponse {
+    if !state.authorized(&req) {
+        return login_redirect(&path.0);
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(state.fixtures.render("start.html", today()))
+}
+
+#[get("/{symbol}/{school_id}/Home.mvc/RefreshSession")]
+async fn refresh_session(req: HttpRequest, state: web::Data<MockState>) -> HttpResponse {
+    let new_cookie = session_cookie(&req).and_then(|cookie| state.sessions().rotate(&cookie));
+
+    HttpResponse::Ok()
+        .insert_header((
+            header::SET_COOKIE,
+            format!(
+                "EfebSsoCookie={}; domain=.vulcan.net.pl; path=/",
+                new_cookie.as_deref().unwrap_or("null")
+            ),
+        ))
+        .content_type("application/json; charset=utf-8")
+        .body(r#"{"success":true}"#)
+}
+
+#[post("/{symbol}/Start.mvc/GetKidsLessonPlan")]
+async fn kids_lesson_plan(
+    symbol: web::Path<String>,
+    req: HttpRequest,
+    state: web::Data<MockState>,
+) -> HttpResponse {
+    if !state.authorized(&req) {
+        return login_redirect(&symbol);
+    }
+
+    json(state.fixtures.render("lesson_plan.json", today()))
+}
+
+#[post("/{symbol}/Start.mvc/GetLastTests")]
+async fn last_tests(
+    symbol: web::Path<String>,
+    req: HttpRequest,
+    state: web::Data<MockState>,
+) -> HttpResponse {
+    if !state.authorized(&req) {
+        return login_redirect(&symbol);
+    }
+
+    json(state.fixtures.render("last_tests.json", today()))
+}
+
+#[derive(Deserialize)]
+struct WeekPlanRequest {
+    /// The week's Monday, as `2022-09-05T00:00:00`.
+    data: String,
+}
+
+#[post("/{symbol}/{school_id}/PlanZajec.mvc/Get")]
+async fn week_plan(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<MockState>,
+) -> HttpResponse {
+    if !state.authorized(&req) {
+        return login_redirect(&path.0);
+    }
+
+    let monday = serde_json::from_slice::<WeekPlanRequest>(&body)
+        .ok()
+        .and_then(|request| NaiveDate::parse_from_str(request.data.get(..10)?, "%Y-%m-%d").ok());
+
+    let Some(monday) = monday else {
+        return json(r#"{"success":false,"feedback":{"Message":"Invalid date"}}"#.to_owned());
+    };
+
+    json(state.fixtures.render("week_plan.json", monday))
+}
+
+#[post("/{symbol}/{school_id}/UczenDziennik.mvc/Get")]
+async fn diaries(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    state: web::Data<MockState>,
+) -> HttpResponse {
+    if !state.authorized(&req) {
+        return login_redirect(&path.0);
+    }
+
+    json(state.fixtures.render("diaries.json", today()))
+}
+
+/// The CUFS login form, with the attributes in varying order and a non-ASCII title like on the real page.
+fn login_page(error: Option<&str>) -> String {
+    let error = error
+        .map(|error| format!(r#"<div class="ErrorMessage">{error}</div>"#))
+        .unwrap_or_default();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="pl">
+<head><meta charset="utf-8"><title>Logowanie</title></head>
+<body>
+<form method="post" action="LogOn?ReturnUrl=%2Fsign-in">
+<input name="__RequestVerificationToken" type="hidden" value="mock-verification-token">
+<label for="LoginName">Nazwa użytkownika</label>
+<input type="text" title="İdentyfikator lub adres e-mail" id="LoginName" name="LoginName" value="">
+<input value="" name="Password" type="password" id="Password">
+{error}
+<input type="submit" value="Zaloguj się">
+</form>
+</body>
+</html>"#
+    )
+}
+
+/// Where uonetplus sends browsers without a session: to the CUFS sign-in URL.
+#[get("/{symbol}/LoginEndpoint.aspx")]
+async fn login_endpoint(symbol: web::Path<String>) -> HttpResponse {
+    HttpResponse::Found()
+        .insert_header((
+            header::LOCATION,
+            format!(
+                "https://cufs.vulcan.net.pl/{symbol}/FS/LS?wa=wsignin1.0\
+                &wtrealm=https%3a%2f%2fuonetplus.vulcan.net.pl%2f{symbol}%2fLoginEndpoint.aspx"
+            ),
+        ))
+        .finish()
+}
+
+/// The sign-in URL, which redirects on to the login form.
+#[get("/{symbol}/FS/LS")]
+async fn sign_in(symbol: web::Path<String>) -> HttpResponse {
+    HttpResponse::Found()
+        .insert_header((
+            header::LOCATION,
+            format!("/{symbol}/Account/LogOn?ReturnUrl=%2F{symbol}%2FFS%2FLS"),
+        ))
+        .finish()
+}
+
+#[get("/{symbol}/Account/LogOn")]
+async fn login_form() -> HttpResponse {
+    html(login_page(None))
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    #[serde(rename = "LoginName")]
+    login: String,
+    #[serde(rename = "Password")]
+    password: String,
+}
+
+/// Checks the credentials and answers with the WS-Federation form, which a browser would post automatically.
+#[post("/{symbol}/Account/LogOn")]
+async fn log_in(
+    symbol: web::Path<String>,
+    form: web::Form<LoginForm>,
+    state: web::Data<MockState>,
+) -> HttpResponse {
+    if form.login != state.login || form.password != state.password {
+        println!("Rejected the login of {}.", form.login);
+
+        return html(login_page(Some("Zła nazwa użytkownika lub hasło.")));
+    }
+
+    let token = state.sessions().issue_token();
+
+    html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><title>Working...</title></head>
+<body>
+<form method="POST" name="hiddenform" action="https://uonetplus.vulcan.net.pl/{symbol}/LoginEndpoint.aspx">
+<input type="hidden" name="wa" value="wsignin1.0">
+<input value="&lt;trust:RequestSecurityTokenResponseCollection&gt;&lt;saml:Assertion AssertionID=&quot;{token}&quot; /&gt;&lt;/trust:RequestSecurityTokenResponseCollection&gt;" type="hidden" name="wresult">
+<input type="hidden" name="wctx" value="rm=0&amp;id=passive&amp;ru=%2f{symbol}%2fLoginEndpoint.aspx">
+<noscript><p>Script is disabled. Click Submit to continue.</p><input type="submit" value="Submit"></noscript>
+</form>
+<script language="javascript">window.setTimeout('document.forms[0].submit()', 0);</script>
+</body>
+</html>"#
+    ))
+}
+
+#[derive(Deserialize)]
+struct SignInResult {
+    wresult: String,
+}
+
+/// Exchanges the sign-in token in `wresult` for a new session.
+#[post("/{symbol}/LoginEndpoint.aspx")]
+async fn finish_login(
+    symbol: web::Path<String>,
+    form: web::Form<SignInResult>,
+    state: web::Data<MockState>,
+) -> HttpResponse {
+    let token = form
+        .wresult
+        .split("AssertionID=\"")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .unwrap_or_default();
+
+    let mut sessions = state.sessions();
+
+    if !sessions.tokens.remove(token) {
+        return HttpResponse::Forbidden().body("invalid sign-in token");
+    }
+
+    let cookie = sessions.mint();
+    println!("Session {cookie} started by logging in.");
+
+    HttpResponse::Found()
+        .insert_header((
+            header::SET_COOKIE,
+            format!("EfebSsoCookie={cookie}; domain=.vulcan.net.pl; path=/; HttpOnly"),
+        ))
+        .insert_header((header::LOCATION, format!("/{symbol}/Start.mvc/Index")))
+        .finish()
+}
+
+#[post("/mock/expire")]
+async fn expire(state: web::Data<MockState>) -> HttpResponse {
+    state.sessions().valid.clear();
+    println!("All sessions expired.");
+
+    HttpResponse::NoContent().finish()
+}
+
+#[get("/mock/session")]
+async fn new_session(state: web::Data<MockState>) -> HttpResponse {
+    let cookie = state.sessions().mint();
+    println!("Session {cookie} started.");
+
+    HttpResponse::Ok().content_type("text/plain").body(cookie)
+}
+
+pub async fn run(options: Options) -> Result<()> {
+    let mut sessions = Sessions {
+        valid: HashMap::new(),
+        minted: 0,
+        lifetime: options.session_lifetime,
+        tokens: HashSet::new(),
+    };
+    sessions
+        .valid
+        .insert(options.cookie.clone(), Instant::now() + sessions.lifetime);
+
+    let state = web::Data::new(MockState {
+        fixtures: Fixtures::load(options.fixtures.as_deref())?,
+        sessions: Mutex::new(sessions),
+        login: options.login,
+        password: options.password,
+    });
+
+    println!(
+        "Mock Vulcan listening on http://{}, the initial cookie is {}.",
+        options.bind, options.cookie
+    );
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .service(student_panel)
+            .service(refresh_session)
+            .service(kids_lesson_plan)
+            .service(last_tests)
+            .service(week_plan)
+            .service(diaries)
+            .service(login_endpoint)
+            .service(sign_in)
+            .service(login_form)
+            .service(log_in)
+            .service(finish_login)
+            .service(expire)
+            .service(new_session)
+    })
+    .bind(options.bind)?
+    .run()
+    .await?;
+
+    Ok(())
+}
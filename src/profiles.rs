@@ -0,0 +1,253 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    sync::OnceLock,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{Local, NaiveDate};
+use tokio::sync::Mutex;
+
+use crate::{
+    config::{self, ProfileConfig},
+    cookie_refresher::{self, SessionStatus},
+    endpoints::{self, Diary},
+    error::Error,
+    requests::{self, AuthInfo, CalendarCache},
+    revisions::Revisions,
+};
+
+static PROFILES: OnceLock<HashMap<String, Profile>> = OnceLock::new();
+
+/// A single student served under `/{name}/...`, with its own session and calendar cache.
+pub struct Profile {
+    pub config: &'static ProfileConfig,
+    pub auth: Mutex<AuthInfo>,
+    pub cache: Mutex<CalendarCache>,
+    pub session: Mutex<SessionStatus>,
+}
+
+impl Profile {
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+}
+
+pub fn read_cookie(profile: &ProfileConfig) -> Result<String> {
+    Ok(fs::read_to_string(&profile.cookie_file)
+        .with_context(|| format!("Failed to read {}", profile.cookie_file.display()))?
+        .lines()
+        .next()
+        .filter(|cookie| !cookie.is_empty())
+        .context("cookie file is empty")?
+        .to_owned())
+}
+
+/// Saves `cookie` so it survives restarts. Failures are only logged, as the session itself is still usable.
+pub fn write_cookie(profile: &ProfileConfig, cookie: &str) {
+    if let Err(err) = fs::write(&profile.cookie_file, cookie) {
+        eprintln!("Failed to write to cookie file: {err:#?}");
+    }
+}
+
+/// Reads the cookie from the profile's file, logging in with its credentials if there is none.
+async fn initial_cookie(profile: &ProfileConfig) -> Result<String> {
+    let err = match read_cookie(profile) {
+        Ok(cookie) => return Ok(cookie),
+        Err(err) => err,
+    };
+
+    let Some((login, password)) = profile.credentials() else {
+        return Err(err);
+    };
+
+    println!(
+        "No cookie for profile {} ({err:#}), logging in...",
+        profile.name
+    );
+
+    let cookie = requests::login::log_in(login, password)
+        .await
+        .with_context(|| format!("Failed to log in profile {}", profile.name))?;
+
+    write_cookie(profile, &cookie);
+
+    Ok(cookie)
+}
+
+/// Picks the diary a profile uses: the configured student (or the only one on the account) in the configured
+/// school year, or else the one that's currently running.
+pub fn select_diary<'a>(
+    profile: &ProfileConfig,
+    diaries: &'a [Diary],
+    today: NaiveDate,
+) -> Result<&'a Diary> {
+    let candidates = diaries
+        .iter()
+        .filter(|diary| profile.student_id.is_none_or(|id| diary.student_id == id))
+        .filter(|diary| profile.register_id.is_none_or(|id| diary.register_id == id))
+        .filter(|diary| {
+            profile
+                .school_year
+                .is_none_or(|year| diary.school_year == year)
+        })
+        .collect::<Vec<_>>();
+
+    let students = candidates
+        .iter()
+        .map(|diary| {
+            format!(
+                "{} {}: {}",
+                diary.first_name, diary.last_name, diary.student_id
+            )
+        })
+        .collect::<BTreeSet<_>>();
+
+    if students.len() > 1 {
+        bail!(
+            "The account has several students ({}), set `student_id` for profile {}",
+            students.into_iter().collect::<Vec<_>>().join(", "),
+            profile.name
+        );
+    }
+
+    candidates
+        .iter()
+        .find(|diary| diary.contains(today))
+        .or_else(|| candidates.iter().max_by_key(|diary| diary.school_year))
+        .copied()
+        .with_context(|| format!("No diary matches profile {}", profile.name))
+}
+
+/// Fills in the student, register and school year the profile didn't configure.
+pub async fn discover(profile: &ProfileConfig, auth_info: &mut AuthInfo) -> Result<()> {
+    let diaries = endpoints::get_diaries(auth_info).await?;
+    let diary = select_diary(profile, &diaries, Local::now().date_naive())?;
+
+    if (
+        auth_info.student_id,
+        auth_info.register_id,
+        auth_info.school_year,
+    ) != (diary.student_id, diary.register_id, diary.school_year)
+    {
+        println!(
+            "Profile {} uses {} {} (student {}, register {}, school year {}).",
+            profile.name,
+            diary.first_name,
+            diary.last_name,
+            diary.student_id,
+            diary.register_id,
+            diary.school_year
+        );
+    }
+
+    auth_info.student_id = diary.student_id;
+    auth_info.register_id = diary.register_id;
+    auth_info.school_year = diary.school_year;
+
+    Ok(())
+}
+
+/// Requests served before the first refresh need the app parameters as well. If the session already expired,
+/// the refresher recovers it and tries again.
+async fn discover_app_params(profile: &ProfileConfig, auth: &mut AuthInfo) {
+    match requests::app_params::discover(auth).await {
+        Ok(app_params) => auth.app_params = Some(app_params),
+        Err(err) => eprintln!(
+            "Failed to discover the app parameters of profile {}: {err:#}",
+            profile.name
+        ),
+    }
+}
+
+/// Like [`discover`], but recovers a saved cookie that expired first. If that fails too, the profile starts
+/// undiscovered and the refresher discovers it once it recovered the session; only rejected credentials stop
+/// the startup, as retrying won't help with those.
+async fn discover_at_startup(profile: &ProfileConfig, auth: &mut AuthInfo) -> Result<()> {
+    let err = match discover(profile, auth).await {
+        Err(err) if Error::is_session_expired(&err) => err,
+        result => return result,
+    };
+
+    eprintln!(
+        "Session of profile {} expired ({err:#}), recovering...",
+        profile.name
+    );
+
+    match cookie_refresher::recover(auth, profile).await {
+        Ok(()) => {
+            println!("Recovered the session of profile {}.", profile.name);
+
+            discover_app_params(profile, auth).await;
+            discover(profile, auth).await
+        }
+        Err(err) if matches!(Error::classify(&err), Error::InvalidCredentials(_)) => Err(err),
+        Err(err) => {
+            eprintln!(
+                "Failed to recover the session of profile {}, its students are discovered once it is: {err:#}",
+                profile.name
+            );
+
+            Ok(())
+        }
+    }
+}
+
+/// Creates a [`Profile`] for every configured profile, reading the cookies from their files.
+pub async fn init() -> Result<()> {
+    let mut profiles = HashMap::new();
+
+    for profile in &config::get().profiles {
+        let mut auth = AuthInfo {
+            cookie: initial_cookie(profile).await?,
+            student_id: profile.student_id.unwrap_or_default(),
+            register_id: profile.register_id.unwrap_or_default(),
+            school_year: profile.school_year.unwrap_or_default(),
+            app_params: None,
+        };
+
+        discover_app_params(profile, &mut auth).await;
+
+        if profile.needs_discovery() {
+            discover_at_startup(profile, &mut auth)
+                .await
+                .with_context(|| {
+                    format!("Failed to discover students of profile {}", profile.name)
+                })?;
+        }
+
+        profiles.insert(
+            profile.name.clone(),
+            Profile {
+                config: profile,
+                auth: Mutex::new(auth),
+                cache: Mutex::new(CalendarCache {
+                    revisions: Revisions::load(profile.revisions_file.clone()),
+                    ..Default::default()
+                }),
+                session: Mutex::new(SessionStatus::default()),
+            },
+        );
+    }
+
+    if PROFILES.set(profiles).is_err() {
+        panic!("Profiles initialized twice.");
+    }
+
+    Ok(())
+}
+
+pub fn get(name: &str) -> Option<&'static Profile> {
+    PROFILES.get()?.get(name)
+}
+
+pub fn find(name: &str) -> Result<&'static Profile, Error> {
+    get(name).ok_or_else(|| Error::UnknownProfile(name.to_owned()))
+}
+
+pub fn all() -> impl Iterator<Item = &'static Profile> {
+    PROFILES
+        .get()
+        .into_iter()
+        .flat_map(|profiles| profiles.values())
+}
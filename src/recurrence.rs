@@ -0,0 +1,241 @@
+//! Finds the lessons that take place every week, so the template calendar can describe each of them with a single
+//! recurring event instead of one event per week.
+//!
+//! A lesson recurs when the same subject, teacher, room and group appear in the same slot of the same weekday in at
+//! least two of the fetched weeks. The weeks it doesn't appear in, and the ones it's cancelled or replaced in, are
+//! kept as exceptions to the rule; every other lesson stays a single event.
+
+use std::collections::HashMap;
+
+use chrono::{Datelike, NaiveDate, Weekday};
+use serde::Serialize;
+
+use crate::week_plan::{Lesson, LessonStatus, WeekPlan};
+
+/// What makes two lessons in different weeks the same recurring lesson.
+#[derive(PartialEq, Eq, Hash)]
+struct Key {
+    weekday: Weekday,
+    number: u32,
+    subject: String,
+    teacher: Option<String>,
+    room: Option<String>,
+    group: Option<String>,
+}
+
+impl Key {
+    fn of(lesson: &Lesson) -> Self {
+        Self {
+            weekday: lesson.date.weekday(),
+            number: lesson.number,
+            subject: lesson.subject.clone(),
+            teacher: lesson.teacher.clone(),
+            room: lesson.room.clone(),
+            group: lesson.group.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Series {
+    /// The lesson as it regularly takes place, dated on its first occurrence in the fetched weeks.
+    pub lesson: Lesson,
+    /// Days of the fetched weeks that fall on the lesson's weekday but don't have it, e.g. holidays, and the days
+    /// it's replaced by a substitution already shown as the exception of another series, like one for the whole
+    /// class replacing the lessons of both groups.
+    pub missing: Vec<NaiveDate>,
+    /// What takes place instead on the days the lesson is cancelled: the substitution in its slot, or the
+    /// cancelled lesson itself.
+    pub exceptions: Vec<Lesson>,
+}
+
+#[derive(Debug, Default)]
+pub struct Template {
+    pub series: Vec<Series>,
+    /// Lessons that don't recur, and substitutions that don't replace a recurring lesson. Cancelled lessons that
+    /// were substituted are left out, like in the effective calendar.
+    pub single: Vec<Lesson>,
+}
+
+/// Splits the lessons of `weeks` into recurring series and single lessons.
+pub fn detect(weeks: &[WeekPlan]) -> Template {
+    let lessons = weeks
+        .iter()
+        .flat_map(|week| &week.lessons)
+        .collect::<Vec<_>>();
+
+    let mut occurrences = HashMap::<Key, Vec<usize>>::new();
+
+    for (index, lesson) in lessons.iter().enumerate() {
+        if lesson.status != LessonStatus::Substitution {
+            occurrences.entry(Key::of(lesson)).or_default().push(index);
+        }
+    }
+
+    // In order of the first occurrence, so substitutions are matched the same way on every refresh.
+    let mut recurring = occurrences
+        .into_values()
+        .filter(|indices| indices.len() > 1)
+        .collect::<Vec<_>>();
+    recurring.sort();
+
+    let mut used = vec![false; lessons.len()];
+    let mut template = Template::default();
+
+    for indices in &recurring {
+        let first = lessons[indices[0]];
+
+        let mut lesson = indices
+            .iter()
+            .map(|&index| lessons[index])
+            .find(|lesson| lesson.status == LessonStatus::Regular)
+            .unwrap_or(first)
+            .clone();
+
+        lesson.date = first.date;
+        lesson.status = LessonStatus::Regular;
+        lesson.note = None;
+        lesson.changes.clear();
+
+        let mut series = Series {
+            lesson,
+            missing: Vec::new(),
+            exceptions: Vec::new(),
+        };
+
+        let days = weeks
+            .iter()
+            .flat_map(|week| &week.days)
+            .filter(|day| day.weekday() == first.date.weekday());
+
+        for &day in days {
+            if !indices.iter().any(|&index| lessons[index].date == day) {
+                series.missing.push(day);
+            }
+        }
+
+        for &index in indices {
+            used[index] = true;
+
+            let occurrence = lessons[index];
+
+            if occurrence.status != LessonStatus::Cancelled {
+                continue;
+            }
+
+            let substitution = lessons
+                .iter()
+                .position(|other| occurrence.is_replaced_by(other));
+
+            match substitution {
+                Some(substitution) if !used[substitution] => {
+                    used[substitution] = true;
+                    series.exceptions.push(lessons[substitution].clone());
+                }
+                Some(_) => series.missing.push(occurrence.date),
+                None => series.exceptions.push(occurrence.clone()),
+            }
+        }
+
+        series.missing.sort();
+
+        template.series.push(series);
+    }
+
+    template.single = lessons
+        .iter()
+        .zip(used)
+        .filter(|(lesson, used)| {
+            let superseded = lessons.iter().any(|other| lesson.is_replaced_by(other));
+
+            !used && !superseded
+        })
+        .map(|(lesson, _)| (*lesson).clone())
+        .collect();
+
+    template
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{date, template_weeks};
+
+    fn series<'a>(template: &'a Template, subject: &str) -> &'a Series {
+        template
+            .series
+            .iter()
+            .find(|series| series.lesson.subject == subject)
+            .unwrap_or_else(|| panic!("{subject} doesn't recur"))
+    }
+
+    fn exceptions(series: &Series) -> Vec<(NaiveDate, &str, LessonStatus)> {
+        series
+            .exceptions
+            .iter()
+            .map(|lesson| (lesson.date, lesson.subject.as_str(), lesson.status))
+            .collect()
+    }
+
+    #[test]
+    fn excludes_the_weeks_without_the_lesson() {
+        let template = detect(&template_weeks());
+        let mathematics = series(&template, "Matematyka");
+
+        assert_eq!(mathematics.lesson.date, date(10, 5));
+        assert_eq!(mathematics.missing, [date(10, 12)]);
+        assert!(mathematics.exceptions.is_empty());
+    }
+
+    #[test]
+    fn overrides_cancellations_with_their_substitutions() {
+        let template = detect(&template_weeks());
+
+        let physics = series(&template, "Fizyka");
+        assert_eq!(physics.lesson.status, LessonStatus::Regular);
+        assert!(physics.missing.is_empty());
+        assert_eq!(
+            exceptions(physics),
+            [(date(10, 12), "Fizyka", LessonStatus::Cancelled)]
+        );
+
+        assert_eq!(
+            exceptions(series(&template, "Chemia")),
+            [(date(10, 19), "Biologia", LessonStatus::Substitution)]
+        );
+    }
+
+    #[test]
+    fn shows_a_substitution_for_both_groups_once() {
+        let template = detect(&template_weeks());
+
+        let english = series(&template, "Język angielski");
+        assert_eq!(english.lesson.group.as_deref(), Some("1/2"));
+        assert_eq!(
+            exceptions(english),
+            [(
+                date(10, 12),
+                "Wychowanie fizyczne",
+                LessonStatus::Substitution
+            )]
+        );
+
+        let german = series(&template, "Język niemiecki");
+        assert!(german.exceptions.is_empty());
+        assert_eq!(german.missing, [date(10, 12)]);
+    }
+
+    #[test]
+    fn keeps_lessons_that_dont_recur_single() {
+        let template = detect(&template_weeks());
+
+        let single = template
+            .single
+            .iter()
+            .map(|lesson| lesson.subject.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(template.series.len(), 5);
+        assert_eq!(single, ["Zajęcia wyrównawcze"]);
+    }
+}
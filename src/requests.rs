@@ -1,17 +1,30 @@
+use std::collections::HashMap;
+
 use anyhow::{Context, Result};
-use chrono::{DateTime, Local};
-use hyper::{body::HttpBody, Body, Client, HeaderMap, Method, Request, Response};
-use hyper_rustls::ConfigBuilderExt;
-use lazy_static::lazy_static;
-use rustls::client::ServerCertVerifier;
-use std::{sync::Arc, time::SystemTime};
-use tokio::sync::Mutex;
+use bytes::Bytes;
+use chrono::{DateTime, Local, NaiveDate};
+use hyper::{header, Body, HeaderMap, Method, Request, Response};
+use serde::de::DeserializeOwned;
+
+use crate::{config, error::Error, revisions::Revisions, week_plan::WeekPlan};
+
+pub mod app_params;
+pub mod circuit_breaker;
+pub mod client;
+pub mod login;
+pub mod recording;
+pub mod retry;
+pub mod transport;
+
+use app_params::AppParams;
 
 pub struct AuthInfo {
     pub cookie: String,
     pub student_id: u32,
     pub register_id: u32,
     pub school_year: u32,
+    /// Scraped from the student panel, attached to every uonetplus-uczen request once known.
+    pub app_params: Option<AppParams>,
 }
 
 impl Default for AuthInfo {
@@ -21,63 +34,51 @@ impl Default for AuthInfo {
             student_id: Default::default(),
             register_id: Default::default(),
             school_year: 2022,
+            app_params: None,
         }
     }
 }
 
+#[derive(Default)]
 pub struct CalendarCache {
-    pub last_updated: Option<DateTime<Local>>,
-    pub regular_calendar: Option<String>,
-    pub replacements_calendar: Option<String>,
+    /// Week plans by their Monday.
+    pub weeks: HashMap<NaiveDate, CachedWeek>,
+    pub revisions: Revisions,
+    /// The first and last day of the diary's school year, once fetched.
+    pub term: Option<CachedTerm>,
 }
 
-impl CalendarCache {
-    pub fn is_valid(&self) -> bool {
-        if let Some(last_updated) = self.last_updated {
-            (Local::now()
-                .signed_duration_since(last_updated)
-                .num_minutes()
-                <= 5)
-                && self.replacements_calendar.is_some()
-                && self.regular_calendar.is_some()
-        } else {
-            false
-        }
-    }
+pub struct CachedWeek {
+    pub fetched: DateTime<Local>,
+    pub plan: WeekPlan,
 }
 
-impl Default for CalendarCache {
-    fn default() -> Self {
-        Self {
-            last_updated: None,
-            regular_calendar: None,
-            replacements_calendar: None,
-        }
+impl CachedWeek {
+    pub fn is_valid(&self) -> bool {
+        Local::now()
+            .signed_duration_since(self.fetched)
+            .num_minutes()
+            <= 5
     }
 }
 
-pub enum Group {
-    One,
-    Two,
+pub struct CachedTerm {
+    pub fetched: DateTime<Local>,
+    /// `None` when Vulcan didn't report them.
+    pub dates: Option<(NaiveDate, NaiveDate)>,
 }
 
-const SERVER_IP: &'static str = "https://82.177.190.81";
-
-lazy_static! {
-    pub static ref GROUP_ONE_AUTH: Mutex<AuthInfo> = Mutex::new(AuthInfo {
-        student_id: 4033,
-        register_id: 1403,
-        ..Default::default()
-    });
-    pub static ref GROUP_ONE_CACHE: Mutex<CalendarCache> = Mutex::new(CalendarCache::default());
-    pub static ref GROUP_TWO_AUTH: Mutex<AuthInfo> = Mutex::new(AuthInfo {
-        student_id: 4040,
-        register_id: 1403,
-        ..Default::default()
-    });
-    pub static ref GROUP_TWO_CACHE: Mutex<CalendarCache> = Mutex::new(CalendarCache::default());
+impl CachedTerm {
+    /// Known dates are kept until the school year is over, unknown ones are asked for again after a day.
+    pub fn is_valid(&self) -> bool {
+        match self.dates {
+            Some((_, end)) => end >= Local::now().date_naive(),
+            None => Local::now().signed_duration_since(self.fetched).num_hours() < 24,
+        }
+    }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Host {
     UonetPlus,
     UonetPlusUczen,
@@ -92,71 +93,146 @@ impl std::fmt::Display for Host {
     }
 }
 
-pub struct InsecureVerifier {}
+pub async fn body_text(body: Body) -> Result<String> {
+    String::from_utf8(hyper::body::to_bytes(body).await?.into_iter().collect())
+        .context("Failed to convert body to string")
+}
+
+/// Parses a JSON response, telling an expired session (a redirect or an HTML login page) and an
+/// unavailable server apart from a response that doesn't parse.
+pub async fn read_json<T: DeserializeOwned>(resp: Response<Body>) -> Result<T> {
+    let status = resp.status();
+
+    if status.is_redirection() {
+        let location = resp
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .unwrap_or_default();
+
+        return Err(Error::SessionExpired(format!("redirected to {location}")).into());
+    }
+
+    if status.is_server_error() {
+        return Err(Error::UpstreamUnavailable(format!("Vulcan responded with {status}")).into());
+    }
+
+    let body = body_text(resp.into_body()).await?;
 
-impl ServerCertVerifier for InsecureVerifier {
-    fn verify_server_cert(
-        &self,
-        _end_entity: &rustls::Certificate,
-        _intermediates: &[rustls::Certificate],
-        _server_name: &rustls::ServerName,
-        _scts: &mut dyn Iterator<Item = &[u8]>,
-        _ocsp_response: &[u8],
-        _now: SystemTime,
-    ) -> std::result::Result<rustls::client::ServerCertVerified, rustls::Error> {
-        Ok(rustls::client::ServerCertVerified::assertion())
+    if body.trim_start().starts_with('<') {
+        return Err(Error::SessionExpired("received HTML instead of JSON".to_owned()).into());
     }
+
+    serde_json::from_str(&body).map_err(|err| Error::UpstreamFormat(err.to_string()).into())
 }
 
-pub async fn body_text(body: Body) -> Result<String> {
-    String::from_utf8(hyper::body::to_bytes(body).await?.into_iter().collect())
-        .context("Failed to convert body to string")
+/// Sends a request to `relative_url` on `host` through the configured [`transport::Transport`].
+///
+/// GETs are retried after transient failures; other methods are sent once, see [`send_read_only`].
+pub async fn send(
+    method: Method,
+    host: &str,
+    relative_url: &str,
+    cookie: Option<String>,
+    body: Body,
+    headers: Option<HeaderMap>,
+) -> Result<Response<Body>> {
+    let retryable = method == Method::GET;
+
+    send_with_retry(method, host, relative_url, cookie, body, headers, retryable).await
 }
 
-pub async fn post(
-    relative_url: impl Into<String>,
-    auth_info: &AuthInfo,
-    host: Host,
-    body: Option<impl Into<Body>>,
+/// Like [`send`], but also retries non-GET requests. Only for requests that don't change anything upstream.
+pub async fn send_read_only(
+    method: Method,
+    host: &str,
+    relative_url: &str,
+    cookie: Option<String>,
+    body: Body,
     headers: Option<HeaderMap>,
 ) -> Result<Response<Body>> {
-    let body = match body {
-        None => Body::empty(),
-        Some(v) => v.into(),
+    send_with_retry(method, host, relative_url, cookie, body, headers, true).await
+}
+
+async fn send_with_retry(
+    method: Method,
+    host: &str,
+    relative_url: &str,
+    cookie: Option<String>,
+    body: Body,
+    headers: Option<HeaderMap>,
+    retryable: bool,
+) -> Result<Response<Body>> {
+    let body = hyper::body::to_bytes(body).await?;
+    let max_attempts = if retryable {
+        config::get().retry.max_attempts
+    } else {
+        1
     };
 
-    let url = format!("{}{}", SERVER_IP, relative_url.into());
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
 
-    let mut config = rustls::client::ClientConfig::builder()
-        .with_safe_defaults()
-        .with_native_roots()
-        .with_no_client_auth();
+        circuit_breaker::check(host)?;
 
-    let v = Arc::new(InsecureVerifier {});
-    config.dangerous().set_certificate_verifier(v);
+        let result = send_once(
+            &method,
+            host,
+            relative_url,
+            cookie.as_deref(),
+            body.clone(),
+            headers.clone(),
+        )
+        .await;
 
-    let https = hyper_rustls::HttpsConnectorBuilder::new()
-        .with_tls_config(config)
-        .https_only()
-        .enable_http1()
-        .build();
+        let Some(reason) = retry::transient_failure(&result) else {
+            if result.is_ok() {
+                circuit_breaker::record_success(host);
+            } else {
+                circuit_breaker::record_inconclusive(host);
+            }
 
-    let client: Client<_, Body> = Client::builder().build(https);
+            return result;
+        };
 
+        circuit_breaker::record_failure(host, &reason);
+
+        if attempt >= max_attempts || circuit_breaker::is_open(host) {
+            return result;
+        }
+
+        let delay = retry::backoff(attempt);
+
+        eprintln!(
+            "{method} {host}{} failed ({reason}), retrying in {delay:?} (attempt {attempt} of {max_attempts}).",
+            relative_url.split('?').next().unwrap_or_default()
+        );
+
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn send_once(
+    method: &Method,
+    host: &str,
+    relative_url: &str,
+    cookie: Option<&str>,
+    body: Bytes,
+    headers: Option<HeaderMap>,
+) -> Result<Response<Body>> {
     let mut req = Request::builder()
-        .method(Method::POST)
-        .uri(url)
-        .header("Host", host.to_string())
-        .header(
-            "Cookie",
-            format!(
-                "EfebSsoCookie={}; idBiezacyUczen={}; idBiezacyDziennik={}; biezacyRokSzkolny={}",
-                auth_info.cookie,
-                auth_info.student_id,
-                auth_info.register_id,
-                auth_info.school_year
-                ))
-        .header("Content-Length", body.size_hint().exact().unwrap_or(0));
+        .method(method.clone())
+        .uri(format!("https://{host}{relative_url}"));
+
+    if let Some(cookie) = cookie {
+        req = req.header("Cookie", cookie);
+    }
+
+    if method != Method::GET {
+        req = req.header("Content-Length", body.len());
+    }
 
     let req_headers = req.headers_mut().context("Failed to build request")?;
 
@@ -164,49 +240,75 @@ pub async fn post(
         req_headers.extend(headers);
     }
 
-    client
-        .request(req.body(body)?)
+    transport::get()
+        .send(req.body(Body::from(body))?)
         .await
-        .context("POST request failed")
+        .with_context(|| format!("{method} request failed"))
 }
 
-pub async fn get(
-    relative_url: impl Into<String>,
+/// Adds the app parameters to uonetplus-uczen requests, keeping any explicitly given headers.
+fn with_app_params(
     auth_info: &AuthInfo,
     host: Host,
     headers: Option<HeaderMap>,
-) -> Result<Response<Body>> {
-    let url = format!("{}{}", SERVER_IP, relative_url.into());
-
-    let mut config = rustls::client::ClientConfig::builder()
-        .with_safe_defaults()
-        .with_native_roots()
-        .with_no_client_auth();
-
-    let v = Arc::new(InsecureVerifier {});
-    config.dangerous().set_certificate_verifier(v);
-
-    let https = hyper_rustls::HttpsConnectorBuilder::new()
-        .with_tls_config(config)
-        .https_only()
-        .enable_http1()
-        .build();
-
-    let client: Client<_, Body> = Client::builder().build(https);
+) -> Result<Option<HeaderMap>> {
+    let Some(app_params) = auth_info
+        .app_params
+        .as_ref()
+        .filter(|_| host == Host::UonetPlusUczen)
+    else {
+        return Ok(headers);
+    };
 
-    let mut req = Request::builder()
-        .method(Method::GET)
-        .uri(url)
-        .header("Host", host.to_string())
-        .header("Cookie", format!("EfebSsoCookie={}", auth_info.cookie));
+    let mut all_headers = app_params.headers()?;
+    all_headers.extend(headers.unwrap_or_default());
 
-    let req_headers = req.headers_mut().context("Failed to build request")?;
+    Ok(Some(all_headers))
+}
 
-    if let Some(headers) = headers {
-        req_headers.extend(headers);
-    }
+/// Posts to one of the endpoints that only read data, so the request is retried after transient failures.
+pub async fn post(
+    relative_url: impl Into<String>,
+    auth_info: &AuthInfo,
+    host: Host,
+    body: Option<impl Into<Body>>,
+    headers: Option<HeaderMap>,
+) -> Result<Response<Body>> {
+    let headers = with_app_params(auth_info, host, headers)?;
+    let body = match body {
+        None => Body::empty(),
+        Some(v) => v.into(),
+    };
 
-    let req = req.body(Body::empty())?;
+    send_read_only(
+        Method::POST,
+        &host.to_string(),
+        &relative_url.into(),
+        Some(format!(
+            "EfebSsoCookie={}; idBiezacyUczen={}; idBiezacyDziennik={}; biezacyRokSzkolny={}",
+            auth_info.cookie, auth_info.student_id, auth_info.register_id, auth_info.school_year
+        )),
+        body,
+        headers,
+    )
+    .await
+}
 
-    client.request(req).await.context("GET request failed")
+pub async fn get(
+    relative_url: impl Into<String>,
+    auth_info: &AuthInfo,
+    host: Host,
+    headers: Option<HeaderMap>,
+) -> Result<Response<Body>> {
+    let headers = with_app_params(auth_info, host, headers)?;
+
+    send(
+        Method::GET,
+        &host.to_string(),
+        &relative_url.into(),
+        Some(format!("EfebSsoCookie={}", auth_info.cookie)),
+        Body::empty(),
+        headers,
+    )
+    .await
 }
@@ -0,0 +1,116 @@
+//! The per-deployment values uonetplus-uczen expects in `X-V-*` headers, scraped from the student panel.
+
+use anyhow::{Context, Result};
+use hyper::{header, HeaderMap};
+
+use super::{body_text, get, AuthInfo, Host};
+use crate::{config, error::Error};
+
+#[derive(Clone, Debug)]
+pub struct AppParams {
+    pub app_guid: String,
+    pub app_version: String,
+    pub verification_token: String,
+}
+
+impl AppParams {
+    pub fn headers(&self) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+
+        headers.insert("X-V-AppGuid", self.app_guid.parse()?);
+        headers.insert("X-V-AppVersion", self.app_version.parse()?);
+        headers.insert(
+            "X-V-RequestVerificationToken",
+            self.verification_token.parse()?,
+        );
+
+        Ok(headers)
+    }
+}
+
+/// Loads the student panel and reads the app parameters from its `VParam` script block.
+pub async fn discover(auth_info: &AuthInfo) -> Result<AppParams> {
+    let resp = get(
+        format!(
+            "/{}/{}/Start",
+            config::get().symbol,
+            config::get().school_id
+        ),
+        auth_info,
+        Host::UonetPlusUczen,
+        None,
+    )
+    .await?;
+
+    if resp.status().is_redirection() {
+        let location = resp
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .unwrap_or_default();
+
+        return Err(
+            Error::SessionExpired(format!("student panel redirected to {location}")).into(),
+        );
+    }
+
+    let page = body_text(resp.into_body()).await?;
+
+    parse(&page).context("Student panel didn't contain the app parameters")
+}
+
+fn parse(page: &str) -> Option<AppParams> {
+    Some(AppParams {
+        app_guid: script_param(page, "appGuid")?,
+        app_version: script_param(page, "version")?,
+        verification_token: script_param(page, "antiForgeryToken")
+            .or_else(|| hidden_input(page, "__RequestVerificationToken"))?,
+    })
+}
+
+/// Finds `name: 'value'` (or with double quotes) in the page's inline scripts.
+fn script_param(page: &str, name: &str) -> Option<String> {
+    let mut rest = page;
+
+    while let Some(start) = rest.find(name) {
+        let preceded_by_identifier = rest[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|char| char.is_alphanumeric() || char == '_');
+
+        rest = &rest[start + name.len()..];
+
+        if preceded_by_identifier {
+            continue;
+        }
+
+        let Some(value) = rest.trim_start().strip_prefix(':') else {
+            continue;
+        };
+
+        let value = value.trim_start();
+        let Some(quote) = value
+            .chars()
+            .next()
+            .filter(|char| *char == '\'' || *char == '"')
+        else {
+            continue;
+        };
+
+        let value = &value[1..];
+
+        return Some(value[..value.find(quote)?].to_owned());
+    }
+
+    None
+}
+
+fn hidden_input(page: &str, name: &str) -> Option<String> {
+    let start = page.find(&format!("name=\"{name}\""))?;
+    let tag_end = start + page[start..].find('>')?;
+    let tag = &page[page[..start].rfind('<')?..tag_end];
+
+    let value = &tag[tag.find("value=\"")? + 7..];
+
+    Some(value[..value.find('"')?].to_owned())
+}
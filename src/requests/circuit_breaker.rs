@@ -0,0 +1,179 @@
+//! Per-host circuit breakers, so requests fail immediately instead of piling up while Vulcan is down.
+//!
+//! A breaker opens after `circuit_breaker.failure_threshold` consecutive failed attempts. While it's open,
+//! requests to the host are rejected with [`Error::UpstreamUnavailable`]. After `open_duration` it becomes
+//! half-open: a single trial request is let through, closing the breaker if it succeeds and reopening it if not.
+//! Other requests are rejected until the trial finishes.
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::{config, error::Error};
+
+static BREAKERS: Mutex<BTreeMap<String, CircuitBreaker>> = Mutex::new(BTreeMap::new());
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Requests go through.
+    Closed,
+    /// Too many requests failed; new ones are rejected until `retry_at`.
+    Open,
+    /// The open period is over and a single trial request is let through to see whether the host recovered.
+    HalfOpen,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CircuitBreaker {
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub retry_at: Option<DateTime<Local>>,
+    /// When the trial request of a half-open breaker was let through, while it hasn't finished yet.
+    pub trial_started_at: Option<DateTime<Local>>,
+    /// Attempts sent to the host, including retries.
+    pub attempts: u64,
+    pub failures: u64,
+    /// Requests rejected without being sent because the breaker was open.
+    pub rejected: u64,
+    pub times_opened: u64,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            last_error: None,
+            retry_at: None,
+            trial_started_at: None,
+            attempts: 0,
+            failures: 0,
+            rejected: 0,
+            times_opened: 0,
+        }
+    }
+}
+
+fn with_breaker<T>(host: &str, f: impl FnOnce(&mut CircuitBreaker) -> T) -> T {
+    let mut breakers = BREAKERS.lock().unwrap_or_else(|err| err.into_inner());
+
+    f(breakers.entry(host.to_owned()).or_default())
+}
+
+/// Lets an attempt to `host` through, unless its breaker is open or half-open with its trial still in flight.
+pub fn check(host: &str) -> Result<(), Error> {
+    with_breaker(host, |breaker| {
+        let config = &config::get().circuit_breaker;
+
+        // A trial whose request was dropped never finishes; another one is let through after the open duration.
+        let trial_in_flight = breaker.trial_started_at.is_some_and(|started_at| {
+            Local::now() - started_at
+                < chrono::Duration::from_std(config.open_duration())
+                    .unwrap_or_else(|_| chrono::Duration::seconds(60))
+        });
+
+        if breaker.state == BreakerState::HalfOpen && trial_in_flight {
+            breaker.rejected += 1;
+
+            return Err(Error::UpstreamUnavailable(format!(
+                "{host} failed {} times in a row, waiting for a trial request to finish",
+                breaker.consecutive_failures
+            )));
+        }
+
+        if breaker.state == BreakerState::Open {
+            match breaker.retry_at {
+                Some(retry_at) if retry_at > Local::now() => {
+                    breaker.rejected += 1;
+
+                    return Err(Error::UpstreamUnavailable(format!(
+                        "{host} failed {} times in a row, not retrying before {}",
+                        breaker.consecutive_failures,
+                        retry_at.format("%H:%M:%S")
+                    )));
+                }
+                _ => {
+                    println!("Circuit breaker for {host} is half-open, trying again.");
+                    breaker.state = BreakerState::HalfOpen;
+                }
+            }
+        }
+
+        if breaker.state == BreakerState::HalfOpen {
+            breaker.trial_started_at = Some(Local::now());
+        }
+
+        breaker.attempts += 1;
+
+        Ok(())
+    })
+}
+
+pub fn is_open(host: &str) -> bool {
+    with_breaker(host, |breaker| breaker.state == BreakerState::Open)
+}
+
+pub fn record_success(host: &str) {
+    with_breaker(host, |breaker| {
+        if breaker.state != BreakerState::Closed {
+            println!("Circuit breaker for {host} closed, the host responds again.");
+        }
+
+        breaker.state = BreakerState::Closed;
+        breaker.consecutive_failures = 0;
+        breaker.retry_at = None;
+        breaker.trial_started_at = None;
+    });
+}
+
+/// Ends an attempt that failed before reaching the host, e.g. because the request couldn't be built, which says
+/// nothing about whether the host recovered.
+pub fn record_inconclusive(host: &str) {
+    with_breaker(host, |breaker| breaker.trial_started_at = None);
+}
+
+pub fn record_failure(host: &str, reason: &str) {
+    let config = &config::get().circuit_breaker;
+
+    with_breaker(host, |breaker| {
+        breaker.consecutive_failures += 1;
+        breaker.failures += 1;
+        breaker.last_error = Some(reason.to_owned());
+        breaker.trial_started_at = None;
+
+        let should_open = match breaker.state {
+            BreakerState::Closed => breaker.consecutive_failures >= config.failure_threshold,
+            BreakerState::HalfOpen => true,
+            // Requests that were already in flight when it opened.
+            BreakerState::Open => false,
+        };
+
+        if should_open {
+            let retry_at = Local::now()
+                + chrono::Duration::from_std(config.open_duration())
+                    .unwrap_or_else(|_| chrono::Duration::seconds(60));
+
+            eprintln!(
+                "Circuit breaker for {host} opened after {} consecutive failures ({reason}), \
+                pausing requests until {}.",
+                breaker.consecutive_failures,
+                retry_at.format("%H:%M:%S")
+            );
+
+            breaker.state = BreakerState::Open;
+            breaker.retry_at = Some(retry_at);
+            breaker.times_opened += 1;
+        }
+    });
+}
+
+/// The breakers of every host contacted so far, keyed by hostname.
+pub fn snapshot() -> BTreeMap<String, CircuitBreaker> {
+    BREAKERS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+}
@@ -0,0 +1,220 @@
+//! The hyper-based [`Transport`], shared by every upstream request so connections are pooled and kept alive.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{self, Poll},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use hyper::{
+    client::{
+        connect::dns::{GaiResolver, Name},
+        HttpConnector,
+    },
+    header::{self, HeaderValue},
+    service::Service,
+    Body, Client, HeaderMap, Request, Response, Uri,
+};
+use hyper_rustls::HttpsConnector;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, RootCertStore, ServerName,
+};
+
+use super::transport::{ResponseFuture, Transport};
+use crate::config::Config;
+
+pub struct HttpClient {
+    client: Client<HttpsConnector<HttpConnector<OverrideResolver>>, Body>,
+    request_timeout: Duration,
+    default_headers: HeaderMap,
+    base_url: Option<String>,
+}
+
+impl HttpClient {
+    pub fn new(config: &Config) -> Result<Self> {
+        let mut roots = RootCertStore::empty();
+
+        for cert in rustls_native_certs::load_native_certs()
+            .context("Failed to load the root certificates")?
+        {
+            // Some system stores contain certificates webpki can't parse; those are skipped.
+            let _ = roots.add(&Certificate(cert.0));
+        }
+
+        let pins = config.upstream.pins();
+
+        let tls = if pins.is_empty() {
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_no_client_auth()
+        } else {
+            let mut tls = rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots.clone())
+                .with_no_client_auth();
+
+            tls.dangerous()
+                .set_certificate_verifier(Arc::new(PinningVerifier {
+                    inner: WebPkiVerifier::new(roots, None),
+                    pins,
+                }));
+
+            tls
+        };
+
+        let mut http = HttpConnector::new_with_resolver(OverrideResolver {
+            overrides: Arc::new(config.upstream.resolve.clone()),
+            fallback: GaiResolver::new(),
+        });
+        http.enforce_http(false);
+        http.set_connect_timeout(Some(config.http.connect_timeout()));
+        http.set_keepalive(Some(config.http.pool_idle_timeout()));
+        http.set_nodelay(true);
+
+        // Plain HTTP is only ever used when `upstream.base_url` points at a local mock.
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(tls)
+            .https_or_http()
+            .enable_http1();
+
+        let https = if config.http.http2 {
+            https.enable_http2().wrap_connector(http)
+        } else {
+            https.wrap_connector(http)
+        };
+
+        let client = Client::builder()
+            .pool_idle_timeout(config.http.pool_idle_timeout())
+            .pool_max_idle_per_host(config.http.pool_max_idle_per_host)
+            .build(https);
+
+        let mut default_headers = HeaderMap::new();
+
+        if let Some(user_agent) = &config.http.user_agent {
+            default_headers.insert(header::USER_AGENT, HeaderValue::from_str(user_agent)?);
+        }
+
+        Ok(Self {
+            client,
+            request_timeout: config.http.request_timeout(),
+            default_headers,
+            base_url: config.upstream.base_url.clone(),
+        })
+    }
+
+    /// Sends `req`, adding the default headers it doesn't set itself. Times out once the response
+    /// headers take longer than the configured request timeout.
+    async fn request(&self, mut req: Request<Body>) -> Result<Response<Body>> {
+        if let Some(base_url) = &self.base_url {
+            let host = req.uri().host().unwrap_or_default().to_owned();
+            let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+
+            *req.uri_mut() = format!("{base_url}{path}")
+                .parse::<Uri>()
+                .context("Invalid upstream.base_url")?;
+            req.headers_mut()
+                .insert(header::HOST, HeaderValue::from_str(&host)?);
+        }
+
+        for (name, value) in &self.default_headers {
+            if !req.headers().contains_key(name) {
+                req.headers_mut().insert(name, value.clone());
+            }
+        }
+
+        tokio::time::timeout(self.request_timeout, self.client.request(req))
+            .await
+            .with_context(|| format!("Request timed out after {:?}", self.request_timeout))?
+            .map_err(Into::into)
+    }
+}
+
+impl Transport for HttpClient {
+    fn send(&self, req: Request<Body>) -> ResponseFuture<'_> {
+        Box::pin(self.request(req))
+    }
+}
+
+/// Verifies certificates as usual, then additionally requires the server certificate to be pinned.
+struct PinningVerifier {
+    inner: WebPkiVerifier,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        let fingerprint = ring::digest::digest(&ring::digest::SHA256, &end_entity.0);
+
+        if self.pins.iter().any(|pin| pin == fingerprint.as_ref()) {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::General(
+                "server certificate is not pinned".to_owned(),
+            ))
+        }
+    }
+}
+
+/// Answers lookups of the hostnames in `upstream.resolve` with their configured addresses, and resolves
+/// everything else through the system.
+#[derive(Clone)]
+struct OverrideResolver {
+    overrides: Arc<HashMap<String, IpAddr>>,
+    fallback: GaiResolver,
+}
+
+type ResolveFuture = Pin<
+    Box<
+        dyn Future<
+                Output = std::result::Result<
+                    std::vec::IntoIter<SocketAddr>,
+                    Box<dyn std::error::Error + Send + Sync>,
+                >,
+            > + Send,
+    >,
+>;
+
+impl Service<Name> for OverrideResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = ResolveFuture;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.fallback.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        if let Some(ip) = self.overrides.get(name.as_str()) {
+            let addrs = vec![SocketAddr::new(*ip, 0)].into_iter();
+            return Box::pin(async move { Ok(addrs) });
+        }
+
+        let lookup = self.fallback.call(name);
+
+        Box::pin(async move { Ok(lookup.await?.collect::<Vec<_>>().into_iter()) })
+    }
+}
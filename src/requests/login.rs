@@ -0,0 +1,448 @@
+//! The CUFS/ADFS login form flow, used to mint an `EfebSsoCookie` from a login and a password.
+//!
+//! Logging in is a chain of redirects and HTML forms: uonetplus redirects to the CUFS (or ADFS) login page,
+//! the credentials are posted there, and the returned WS-Federation form (`wa`, `wresult`, `wctx`) is posted
+//! back to uonetplus, which finally sets the cookie. The flow is followed generically, so extra ADFS hops work too.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use hyper::{header, Body, HeaderMap, Method, Response, Uri};
+use thiserror::Error;
+
+use super::{body_text, send, Host};
+use crate::config;
+
+/// Upper bound of redirects and form posts before giving up.
+const MAX_STEPS: usize = 16;
+
+#[derive(Error, Debug)]
+pub enum LoginError {
+    #[error("invalid login or password: {0}")]
+    InvalidCredentials(String),
+    #[error("unexpected page during login: {0}")]
+    UnexpectedPage(String),
+    #[error("login did not finish after {MAX_STEPS} steps")]
+    TooManySteps,
+}
+
+/// Logs in with the given credentials and returns the new `EfebSsoCookie`.
+pub async fn log_in(login: &str, password: &str) -> Result<String> {
+    let mut jar = CookieJar::default();
+    let mut credentials_sent = false;
+
+    let mut host = Host::UonetPlus.to_string();
+    let mut path = format!("/{}/LoginEndpoint.aspx", config::get().symbol);
+    let mut form: Option<Vec<(String, String)>> = None;
+
+    for _ in 0..MAX_STEPS {
+        let resp = match form.take() {
+            None => {
+                send(
+                    Method::GET,
+                    &host,
+                    &path,
+                    jar.header(&host),
+                    Body::empty(),
+                    None,
+                )
+                .await?
+            }
+            Some(fields) => {
+                let mut headers = HeaderMap::new();
+                headers.insert(
+                    header::CONTENT_TYPE,
+                    "application/x-www-form-urlencoded".parse()?,
+                );
+
+                send(
+                    Method::POST,
+                    &host,
+                    &path,
+                    jar.header(&host),
+                    Body::from(serde_urlencoded::to_string(&fields)?),
+                    Some(headers),
+                )
+                .await?
+            }
+        };
+
+        jar.store(&host, &resp);
+
+        if let Some(cookie) = jar.get("EfebSsoCookie") {
+            return Ok(cookie.to_owned());
+        }
+
+        if resp.status().is_redirection() {
+            let location = resp
+                .headers()
+                .get(header::LOCATION)
+                .context("Redirect without a Location header")?
+                .to_str()?;
+
+            (host, path) = resolve(&host, &path, location)?;
+            continue;
+        }
+
+        let page = body_text(resp.into_body()).await?;
+        let forms = parse_forms(&page);
+
+        if let Some(next) = forms
+            .iter()
+            .find(|form| form.field("wresult").is_some() || form.field("SAMLResponse").is_some())
+        {
+            (host, path) = resolve(&host, &path, &next.action)?;
+            form = Some(next.fields.clone());
+            continue;
+        }
+
+        if let Some(login_form) = forms.iter().find(|form| form.field("Password").is_some()) {
+            if credentials_sent {
+                return Err(LoginError::InvalidCredentials(error_message(&page)).into());
+            }
+
+            let fields = login_form
+                .fields
+                .iter()
+                .map(|(name, value)| {
+                    let value = match name.to_lowercase().as_str() {
+                        "loginname" | "username" => login.to_owned(),
+                        "password" => password.to_owned(),
+                        _ => value.clone(),
+                    };
+                    (name.clone(), value)
+                })
+                .collect();
+
+            (host, path) = resolve(&host, &path, &login_form.action)?;
+            form = Some(fields);
+            credentials_sent = true;
+            continue;
+        }
+
+        return Err(LoginError::UnexpectedPage(format!("{host}{path}")).into());
+    }
+
+    Err(LoginError::TooManySteps.into())
+}
+
+/// Resolves a `Location` or form `action` against the current host and path.
+fn resolve(host: &str, path: &str, target: &str) -> Result<(String, String)> {
+    if target.is_empty() {
+        return Ok((host.to_owned(), path.to_owned()));
+    }
+
+    if target.starts_with("http://") || target.starts_with("https://") {
+        let uri: Uri = target.parse().context("Invalid redirect URL")?;
+
+        return Ok((
+            uri.host()
+                .context("Redirect URL without a host")?
+                .to_owned(),
+            uri.path_and_query()
+                .map(|path| path.to_string())
+                .unwrap_or_else(|| "/".to_owned()),
+        ));
+    }
+
+    if target.starts_with('/') {
+        return Ok((host.to_owned(), target.to_owned()));
+    }
+
+    let base = path.split('?').next().unwrap_or(path);
+    let dir = &base[..base.rfind('/').map_or(0, |index| index + 1)];
+
+    Ok((host.to_owned(), format!("{dir}{target}")))
+}
+
+/// Pulls the message out of the `ErrorMessage` element CUFS shows after a failed login.
+fn error_message(page: &str) -> String {
+    page.find("ErrorMessage")
+        .and_then(|start| {
+            let rest = &page[start..];
+            let rest = &rest[rest.find('>')? + 1..];
+            Some(decode_entities(rest[..rest.find('<')?].trim()))
+        })
+        .filter(|message| !message.is_empty())
+        .unwrap_or_else(|| "the login form was shown again".to_owned())
+}
+
+#[derive(Default)]
+struct CookieJar {
+    /// Cookies keyed by the host (or `.domain`) they apply to.
+    cookies: HashMap<String, HashMap<String, String>>,
+}
+
+impl CookieJar {
+    fn store(&mut self, host: &str, resp: &Response<Body>) {
+        for value in resp.headers().get_all(header::SET_COOKIE) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+
+            let Ok(cookie) = cookie::Cookie::parse(value) else {
+                continue;
+            };
+
+            let scope = match cookie.domain() {
+                Some(domain) => format!(".{}", domain.trim_start_matches('.')),
+                None => host.to_owned(),
+            };
+
+            self.cookies
+                .entry(scope)
+                .or_default()
+                .insert(cookie.name().to_owned(), cookie.value().to_owned());
+        }
+    }
+
+    fn header(&self, host: &str) -> Option<String> {
+        let cookies = self
+            .cookies
+            .iter()
+            .filter(|(scope, _)| {
+                scope.as_str() == host
+                    || (scope.starts_with('.')
+                        && (host.ends_with(scope.as_str()) || host == &scope[1..]))
+            })
+            .flat_map(|(_, cookies)| cookies.iter())
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>();
+
+        (!cookies.is_empty()).then(|| cookies.join("; "))
+    }
+
+    /// Returns a cookie set for any host, ignoring the `null` value Vulcan uses to clear cookies.
+    fn get(&self, name: &str) -> Option<&str> {
+        self.cookies
+            .values()
+            .filter_map(|cookies| cookies.get(name))
+            .map(|value| value.as_str())
+            .find(|value| !value.is_empty() && *value != "null")
+    }
+}
+
+struct Form {
+    action: String,
+    fields: Vec<(String, String)>,
+}
+
+impl Form {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Collects every `<form>` on the page with its `action` and named `<input>`s.
+///
+/// This is a plain tag scanner rather than a DOM parser, as the login pages are rarely well-formed.
+fn parse_forms(page: &str) -> Vec<Form> {
+    let mut forms: Vec<Form> = Vec::new();
+    let mut rest = page;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let name = tag
+            .split(|char: char| char.is_whitespace() || char == '/')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+
+        match name.as_str() {
+            "form" => forms.push(Form {
+                action: attribute(tag, "action").unwrap_or_default(),
+                fields: Vec::new(),
+            }),
+            "input" => {
+                let (Some(form), Some(name)) = (forms.last_mut(), attribute(tag, "name")) else {
+                    continue;
+                };
+
+                form.fields
+                    .push((name, attribute(tag, "value").unwrap_or_default()));
+            }
+            _ => {}
+        }
+    }
+
+    forms
+}
+
+/// Reads a quoted attribute from the inside of a tag. `name` is matched regardless of ASCII case.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    for (start, _) in tag.char_indices() {
+        // `get` rejects ranges ending inside a multi-byte character, which can't be part of a match anyway.
+        let matches = tag
+            .get(start..start + name.len())
+            .is_some_and(|candidate| candidate.eq_ignore_ascii_case(name));
+
+        if !matches {
+            continue;
+        }
+
+        let preceded_by_space = tag[..start]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_whitespace);
+
+        let after = tag[start + name.len()..].trim_start();
+
+        if !preceded_by_space || !after.starts_with('=') {
+            continue;
+        }
+
+        let value = after[1..].trim_start();
+        let quote = value.chars().next()?;
+
+        let value = if quote == '"' || quote == '\'' {
+            &value[1..1 + value[1..].find(quote)?]
+        } else {
+            value.split_whitespace().next()?
+        };
+
+        return Some(decode_entities(value));
+    }
+
+    None
+}
+
+/// Decodes the HTML entities found in attribute values, most importantly the escaped XML in `wresult`.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+
+        let char = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        });
+
+        match (char, entity) {
+            (Some(char), Some(entity)) => {
+                decoded.push(char);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_attributes_in_any_order_and_case() {
+        let tag = r#"input VALUE="a&amp;b" type='hidden' name="wresult""#;
+
+        assert_eq!(attribute(tag, "value").as_deref(), Some("a&b"));
+        assert_eq!(attribute(tag, "type").as_deref(), Some("hidden"));
+        assert_eq!(attribute(tag, "name").as_deref(), Some("wresult"));
+        assert_eq!(attribute(tag, "id"), None);
+    }
+
+    #[test]
+    fn reads_attributes_after_characters_that_change_length_when_lowercased() {
+        // `İ` takes two bytes, but three once lowercased.
+        let tag = r#"input title="İİİ Adres e-mail" name="LoginName" value="""#;
+
+        assert_eq!(attribute(tag, "name").as_deref(), Some("LoginName"));
+        assert_eq!(attribute(tag, "value").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn finds_the_login_and_ws_federation_forms() {
+        let page = r#"
+            <form method="post" action="LogOn?ReturnUrl=%2F">
+            <input name="__RequestVerificationToken" type="hidden" value="token">
+            <input type="text" title="İdentyfikator" name="LoginName" value="">
+            <input value="" name="Password" type="password">
+            <input type="submit" value="Zaloguj">
+            </form>
+            <form method="POST" action="https://uonetplus.vulcan.net.pl/gmina/LoginEndpoint.aspx">
+            <input type="hidden" name="wa" value="wsignin1.0">
+            <input value="&lt;saml:Assertion /&gt;" type="hidden" name="wresult">
+            </form>"#;
+
+        let forms = parse_forms(page);
+
+        assert_eq!(forms.len(), 2);
+        assert_eq!(forms[0].action, "LogOn?ReturnUrl=%2F");
+        assert_eq!(forms[0].field("password"), Some(""));
+        assert_eq!(forms[0].fields.len(), 3);
+        assert_eq!(forms[1].field("wresult"), Some("<saml:Assertion />"));
+    }
+
+    #[test]
+    fn follows_absolute_root_relative_and_relative_redirects() {
+        let host = "uonetplus.vulcan.net.pl";
+
+        let (host, path) = resolve(
+            host,
+            "/gmina/LoginEndpoint.aspx",
+            "https://cufs.vulcan.net.pl/gmina/FS/LS?wa=wsignin1.0",
+        )
+        .unwrap();
+        assert_eq!(
+            (host.as_str(), path.as_str()),
+            ("cufs.vulcan.net.pl", "/gmina/FS/LS?wa=wsignin1.0")
+        );
+
+        let (host, path) = resolve(&host, &path, "/gmina/Account/LogOn?ReturnUrl=%2F").unwrap();
+        assert_eq!(path, "/gmina/Account/LogOn?ReturnUrl=%2F");
+
+        let (host, path) = resolve(&host, &path, "LogOn?ReturnUrl=%2Fsign-in").unwrap();
+        assert_eq!(
+            (host.as_str(), path.as_str()),
+            (
+                "cufs.vulcan.net.pl",
+                "/gmina/Account/LogOn?ReturnUrl=%2Fsign-in"
+            )
+        );
+
+        assert_eq!(resolve(&host, &path, "").unwrap(), (host.clone(), path));
+    }
+
+    #[test]
+    fn reads_the_error_of_a_failed_login() {
+        let page =
+            r#"<form><div class="ErrorMessage"> Zła nazwa użytkownika lub hasło. </div></form>"#;
+
+        assert_eq!(error_message(page), "Zła nazwa użytkownika lub hasło.");
+        assert_eq!(
+            error_message("<form></form>"),
+            "the login form was shown again"
+        );
+    }
+}
@@ -0,0 +1,424 @@
+//! Records upstream responses into a file that `upstream.canned_responses` can replay, so a broken page can be
+//! attached to a bug report and the problem reproduced offline.
+//!
+//! Only responses are stored, never request bodies (which hold passwords during login). Cookie values and the
+//! tokens Vulcan embeds in pages and redirects are replaced with [`REDACTED`].
+
+use std::{fs, ops::Range, path::PathBuf, sync::Mutex};
+
+use anyhow::{Context, Result};
+use hyper::{header, Body, Request, Response};
+
+use super::transport::{request_key, CannedResponse, ResponseFuture, Transport};
+
+pub const REDACTED: &str = "REDACTED";
+
+/// Names of form fields, script parameters and query parameters whose values are credentials: the anti-forgery
+/// token and the WS-Federation/SAML tokens posted back during login.
+const SENSITIVE_FIELDS: &[&str] = &[
+    "antiForgeryToken",
+    "__RequestVerificationToken",
+    "wresult",
+    "SAMLResponse",
+];
+
+/// Headers describing the original transfer rather than the response; replaying them would be wrong.
+const TRANSFER_HEADERS: &[header::HeaderName] = &[
+    header::CONTENT_LENGTH,
+    header::TRANSFER_ENCODING,
+    header::CONNECTION,
+];
+
+pub struct RecordingTransport {
+    inner: Box<dyn Transport>,
+    path: PathBuf,
+    /// In the order the requests were sent, so concurrent requests replay the same way. Requests still waiting
+    /// for their response, or that failed, are `None`.
+    recorded: Mutex<Vec<Option<CannedResponse>>>,
+}
+
+impl RecordingTransport {
+    /// Sends requests through `inner`, recording the responses to `path`. The file is overwritten.
+    pub fn new(inner: Box<dyn Transport>, path: PathBuf) -> Result<Self> {
+        fs::write(&path, "[]").with_context(|| format!("Failed to write {}", path.display()))?;
+
+        Ok(Self {
+            inner,
+            path,
+            recorded: Mutex::new(Vec::new()),
+        })
+    }
+
+    async fn record(&self, req: Request<Body>) -> Result<Response<Body>> {
+        let method = req.method().to_string();
+        let host = req.uri().host().unwrap_or_default().to_owned();
+        let path = req
+            .uri()
+            .path_and_query()
+            .map_or("/", |path| path.as_str())
+            .to_owned();
+
+        let (parts, body) = req.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        let key = request_key(&body);
+        let req = Request::from_parts(parts, Body::from(body));
+
+        let slot = {
+            let mut recorded = self.recorded.lock().unwrap_or_else(|err| err.into_inner());
+            recorded.push(None);
+            recorded.len() - 1
+        };
+
+        let resp = self.inner.send(req).await?;
+        let (parts, body) = resp.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+
+        let headers = parts
+            .headers
+            .iter()
+            .filter(|(name, _)| !TRANSFER_HEADERS.contains(name))
+            .map(|(name, value)| {
+                let value = String::from_utf8_lossy(value.as_bytes());
+                let value = if name == header::SET_COOKIE {
+                    redact_cookie(&value)
+                } else if name == header::LOCATION {
+                    redact_location(&value)
+                } else {
+                    value.into_owned()
+                };
+
+                (name.to_string(), value)
+            })
+            .collect();
+
+        let response = CannedResponse {
+            method,
+            host,
+            // Cache busters like RefreshSession's `_dc` would keep the recording from matching on replay.
+            path: path.split('?').next().unwrap_or_default().to_owned(),
+            key,
+            status: parts.status.as_u16(),
+            headers,
+            body: redact_body(&String::from_utf8_lossy(&body)),
+        };
+
+        {
+            let mut recorded = self.recorded.lock().unwrap_or_else(|err| err.into_inner());
+            recorded[slot] = Some(response);
+
+            let responses = recorded.iter().flatten().collect::<Vec<_>>();
+
+            if let Err(err) = fs::write(&self.path, serde_json::to_string_pretty(&responses)?) {
+                eprintln!("Failed to write the recording: {err:#?}");
+            }
+        }
+
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn send(&self, req: Request<Body>) -> ResponseFuture<'_> {
+        Box::pin(self.record(req))
+    }
+}
+
+/// Replaces the value of a `Set-Cookie` header, keeping the empty and `null` values Vulcan clears cookies with,
+/// as those tell an expired session apart.
+fn redact_cookie(set_cookie: &str) -> String {
+    let (pair, attributes) = set_cookie
+        .split_once(';')
+        .map_or((set_cookie, None), |(pair, attributes)| {
+            (pair, Some(attributes))
+        });
+
+    let Some((name, value)) = pair.split_once('=') else {
+        return set_cookie.to_owned();
+    };
+
+    let value = match value.trim() {
+        "" | "null" => value,
+        _ => REDACTED,
+    };
+
+    match attributes {
+        Some(attributes) => format!("{name}={value};{attributes}"),
+        None => format!("{name}={value}"),
+    }
+}
+
+/// Replaces the values of the query parameters in [`SENSITIVE_FIELDS`] in a `Location` header, e.g. a token
+/// passed along a login redirect.
+fn redact_location(location: &str) -> String {
+    let (url, fragment) = location
+        .split_once('#')
+        .map_or((location, None), |(url, fragment)| (url, Some(fragment)));
+
+    let Some((path, query)) = url.split_once('?') else {
+        return location.to_owned();
+    };
+
+    let query = query
+        .split('&')
+        .map(|param| match param.split_once('=') {
+            Some((name, _)) if is_sensitive(name) => format!("{name}={REDACTED}"),
+            _ => param.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+
+    match fragment {
+        Some(fragment) => format!("{path}?{query}#{fragment}"),
+        None => format!("{path}?{query}"),
+    }
+}
+
+fn is_sensitive(name: &str) -> bool {
+    SENSITIVE_FIELDS
+        .iter()
+        .any(|field| field.eq_ignore_ascii_case(name))
+}
+
+/// Replaces the values of [`SENSITIVE_FIELDS`], both as `<input name="..." value="...">`, with the attributes
+/// in any order, and as `name: '...'` script parameters.
+fn redact_body(body: &str) -> String {
+    let mut values = tag_values(body);
+    values.extend(script_values(body));
+    values.sort_by_key(|range| range.start);
+
+    let mut redacted = String::with_capacity(body.len());
+    let mut copied = 0;
+
+    for range in values {
+        // A value found both ways, e.g. a tag inside a script.
+        if range.start < copied {
+            continue;
+        }
+
+        redacted.push_str(&body[copied..range.start]);
+        redacted.push_str(REDACTED);
+        copied = range.end;
+    }
+
+    redacted.push_str(&body[copied..]);
+
+    redacted
+}
+
+/// Where the `value` attributes of tags named after a sensitive field are in `body`.
+fn tag_values(body: &str) -> Vec<Range<usize>> {
+    let mut values = Vec::new();
+    let mut search_from = 0;
+
+    while let Some(found) = body[search_from..].find('<') {
+        let start = search_from + found;
+        let Some(length) = body[start..].find('>') else {
+            break;
+        };
+        // Not past the `>`, as a `<` in a script could make a tag seem to swallow the real ones after it.
+        search_from = start + 1;
+
+        let attributes = attributes(&body[start..start + length]);
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+                .map(|(_, value)| start + value.start..start + value.end)
+        };
+
+        let sensitive = attribute("name").is_some_and(|name| is_sensitive(&body[name]));
+
+        if let Some(value) = attribute("value").filter(|_| sensitive) {
+            values.push(value);
+        }
+    }
+
+    values
+}
+
+/// The names of the attributes of `tag` (without the closing `>`) and where their values are, without quotes.
+fn attributes(tag: &str) -> Vec<(&str, Range<usize>)> {
+    let bytes = tag.as_bytes();
+    let skip = |mut index: usize, while_: fn(u8) -> bool| {
+        while index < bytes.len() && while_(bytes[index]) {
+            index += 1;
+        }
+        index
+    };
+
+    let mut attributes = Vec::new();
+
+    // Past the `<` and the tag name.
+    let mut index = skip(1, |byte| !byte.is_ascii_whitespace());
+
+    while index < bytes.len() {
+        index = skip(index, |byte| byte.is_ascii_whitespace() || byte == b'/');
+
+        let name_start = index;
+        index = skip(index, |byte| {
+            !(byte.is_ascii_whitespace() || byte == b'=' || byte == b'/')
+        });
+        let name = &tag[name_start..index];
+
+        index = skip(index, |byte| byte.is_ascii_whitespace());
+
+        if bytes.get(index) != Some(&b'=') {
+            if name.is_empty() {
+                index += 1;
+            }
+            continue;
+        }
+
+        index = skip(index + 1, |byte| byte.is_ascii_whitespace());
+
+        let value = match bytes.get(index) {
+            Some(&quote @ (b'"' | b'\'')) => {
+                let start = index + 1;
+                let end = tag[start..]
+                    .find(char::from(quote))
+                    .map_or(tag.len(), |length| start + length);
+                index = end + 1;
+                start..end
+            }
+            _ => {
+                let start = index;
+                index = skip(index, |byte| !byte.is_ascii_whitespace());
+                start..index
+            }
+        };
+
+        attributes.push((name, value));
+    }
+
+    attributes
+}
+
+/// Where the quoted values of `field: '...'` script parameters of sensitive fields are in `body`.
+fn script_values(body: &str) -> Vec<Range<usize>> {
+    let mut values = Vec::new();
+
+    for field in SENSITIVE_FIELDS {
+        let mut search_from = 0;
+
+        while let Some(found) = body[search_from..].find(field) {
+            let after_name = search_from + found + field.len();
+            search_from = after_name;
+
+            let rest = &body[after_name..];
+            let Some(value) = rest
+                .trim_start_matches(['"', '\''])
+                .trim_start()
+                .strip_prefix(':')
+                .map(str::trim_start)
+            else {
+                continue;
+            };
+
+            let Some(quote) = value
+                .chars()
+                .next()
+                .filter(|char| matches!(char, '"' | '\''))
+            else {
+                continue;
+            };
+
+            let start = after_name + rest.len() - value.len() + 1;
+
+            if let Some(length) = body[start..].find(quote) {
+                values.push(start..start + length);
+                search_from = start + length;
+            }
+        }
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Answers with the request body, the first request taking longer than the others.
+    struct EchoTransport;
+
+    impl Transport for EchoTransport {
+        fn send(&self, req: Request<Body>) -> ResponseFuture<'_> {
+            Box::pin(async move {
+                let body = hyper::body::to_bytes(req.into_body()).await?;
+
+                if body.as_ref() == br#"{"data":"2026-10-12T00:00:00"}"# {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+
+                Ok(Response::new(Body::from(body)))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn records_in_request_order_with_the_request_key() {
+        let path =
+            std::env::temp_dir().join(format!("uonetplan-recording-{}.json", std::process::id()));
+        let transport = RecordingTransport::new(Box::new(EchoTransport), path.clone()).unwrap();
+
+        let request = |date: &str| {
+            Request::post("https://uonetplus-uczen.vulcan.net.pl/s/123456/PlanZajec.mvc/Get")
+                .body(Body::from(format!(r#"{{"data":"{date}T00:00:00"}}"#)))
+                .unwrap()
+        };
+
+        let (first, second) = tokio::join!(
+            transport.send(request("2026-10-12")),
+            transport.send(request("2026-10-19"))
+        );
+        first.unwrap();
+        second.unwrap();
+
+        let recorded: Vec<CannedResponse> =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let _ = fs::remove_file(&path);
+
+        let keys = recorded
+            .iter()
+            .map(|response| response.key.as_deref())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            keys,
+            [Some("2026-10-12T00:00:00"), Some("2026-10-19T00:00:00")]
+        );
+    }
+
+    #[test]
+    fn redacts_input_values_in_either_attribute_order() {
+        let body = r#"<form>
+<input type="hidden" name="wresult" value="&lt;trust:RequestSecurityTokenResponse&gt;" />
+<input value='PHNhbWxwOlJlc3BvbnNl' type=hidden name=SAMLResponse>
+<input name="wctx" value="rm=0&amp;id=passive" />
+</form>
+<script>window.VParam = { antiForgeryToken: 'abc123', appGuid: 'c8f8a4b5' };</script>"#;
+
+        assert_eq!(
+            redact_body(body),
+            r#"<form>
+<input type="hidden" name="wresult" value="REDACTED" />
+<input value='REDACTED' type=hidden name=SAMLResponse>
+<input name="wctx" value="rm=0&amp;id=passive" />
+</form>
+<script>window.VParam = { antiForgeryToken: 'REDACTED', appGuid: 'c8f8a4b5' };</script>"#
+        );
+    }
+
+    #[test]
+    fn redacts_tokens_in_redirects() {
+        assert_eq!(
+            redact_location(
+                "https://cufs.vulcan.net.pl/s/FS/LS?wa=wsignin1.0&wresult=abc&wctx=x#top"
+            ),
+            "https://cufs.vulcan.net.pl/s/FS/LS?wa=wsignin1.0&wresult=REDACTED&wctx=x#top"
+        );
+        assert_eq!(redact_location("/s/Start.mvc/Index"), "/s/Start.mvc/Index");
+    }
+}
@@ -0,0 +1,87 @@
+//! Deciding whether a failed upstream attempt is worth repeating, and how long to wait before doing so.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use hyper::{Body, Response, StatusCode};
+use rand::Rng;
+
+use crate::config;
+
+/// Describes why an attempt failed in a way another attempt might fix: a connection error, a timeout, a
+/// server error or rate limiting. Redirects and client errors are answers, and other errors, like a request that
+/// couldn't be built, would fail the same way again; both are left to the caller.
+pub fn transient_failure(result: &Result<Response<Body>>) -> Option<String> {
+    match result {
+        Err(err) if is_connection_error(err) => Some(format!("{err:#}")),
+        Err(_) => None,
+        Ok(resp)
+            if resp.status().is_server_error()
+                || resp.status() == StatusCode::TOO_MANY_REQUESTS =>
+        {
+            Some(format!("responded with {}", resp.status()))
+        }
+        Ok(_) => None,
+    }
+}
+
+/// Whether `err` comes from connecting to the host, the connection breaking, or the host not answering in time.
+fn is_connection_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause.is::<tokio::time::error::Elapsed>()
+            || cause.downcast_ref::<hyper::Error>().is_some_and(|err| {
+                err.is_connect()
+                    || err.is_timeout()
+                    || err.is_closed()
+                    || err.is_incomplete_message()
+                    || err.is_canceled()
+            })
+    })
+}
+
+/// The delay before the `retry`-th retry (counting from 1): exponential up to `retry.max_delay_ms`, of
+/// which a random half is dropped so requests that failed together don't all come back at once.
+pub fn backoff(retry: u32) -> Duration {
+    let config = &config::get().retry;
+
+    let ceiling = config
+        .base_delay_ms
+        .saturating_mul(1 << retry.saturating_sub(1).min(16))
+        .min(config.max_delay_ms);
+
+    Duration::from_millis(ceiling / 2 + rand::thread_rng().gen_range(0..=ceiling / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn retries_timeouts_but_not_other_errors() {
+        let elapsed = tokio::time::timeout(Duration::from_millis(1), std::future::pending::<()>())
+            .await
+            .context("Request timed out")
+            .context("GET request failed")
+            .unwrap_err();
+
+        assert!(transient_failure(&Err(elapsed)).is_some());
+        assert!(transient_failure(&Err(anyhow!("no canned response"))).is_none());
+    }
+
+    #[test]
+    fn retries_server_errors_and_rate_limiting() {
+        let response = |status: StatusCode| {
+            Ok(Response::builder()
+                .status(status)
+                .body(Body::empty())
+                .unwrap())
+        };
+
+        assert!(transient_failure(&response(StatusCode::BAD_GATEWAY)).is_some());
+        assert!(transient_failure(&response(StatusCode::TOO_MANY_REQUESTS)).is_some());
+        assert!(transient_failure(&response(StatusCode::FOUND)).is_none());
+        assert!(transient_failure(&response(StatusCode::NOT_FOUND)).is_none());
+    }
+}
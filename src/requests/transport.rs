@@ -0,0 +1,203 @@
+//! How requests reach Vulcan. Normally through the hyper [`HttpClient`]; with `upstream.canned_responses`
+//! set, a [`MemoryTransport`] answers them from a file instead, so the whole service runs without a network.
+//! With `upstream.record` set, the responses are also written to a file by a [`RecordingTransport`].
+
+use std::{
+    collections::HashSet,
+    fs,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::{Context, Result};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use super::{client::HttpClient, recording::RecordingTransport};
+use crate::config;
+
+static TRANSPORT: OnceLock<Box<dyn Transport>> = OnceLock::new();
+
+pub type ResponseFuture<'a> = Pin<Box<dyn Future<Output = Result<Response<Body>>> + Send + 'a>>;
+
+pub trait Transport: Send + Sync {
+    /// Sends `req`. Its URI always names the real Vulcan host, e.g. `https://uonetplus.vulcan.net.pl/...`.
+    fn send(&self, req: Request<Body>) -> ResponseFuture<'_>;
+}
+
+/// A response served by [`MemoryTransport`] to requests matching its method, host and path.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CannedResponse {
+    pub method: String,
+    pub host: String,
+    /// Compared without the query string, unless it contains one itself.
+    pub path: String,
+    /// Tells apart POSTs to the same path, see [`request_key`]. Matches any request when left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default = "default_status")]
+    pub status: u16,
+    /// Pairs rather than a map, as `Set-Cookie` may appear several times.
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    #[serde(default)]
+    pub body: String,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+/// What tells apart the JSON POSTs Vulcan's endpoints take on the same path: the `data` field of the body, e.g. the
+/// date of the week `PlanZajec.mvc/Get` is asked for. Other bodies, like login forms, have none.
+pub fn request_key(body: &[u8]) -> Option<String> {
+    let body = serde_json::from_slice::<serde_json::Value>(body).ok()?;
+
+    body.get("data")?.as_str().map(str::to_owned)
+}
+
+impl CannedResponse {
+    fn matches(
+        &self,
+        method: &Method,
+        host: &str,
+        path_and_query: &str,
+        key: Option<&str>,
+    ) -> bool {
+        let path = if self.path.contains('?') {
+            path_and_query
+        } else {
+            path_and_query.split('?').next().unwrap_or_default()
+        };
+
+        self.method.eq_ignore_ascii_case(method.as_str())
+            && self.host == host
+            && self.path == path
+            && self
+                .key
+                .as_deref()
+                .is_none_or(|expected| Some(expected) == key)
+    }
+
+    fn to_response(&self) -> Result<Response<Body>> {
+        let mut resp = Response::builder().status(self.status);
+
+        for (name, value) in &self.headers {
+            resp = resp.header(name, value);
+        }
+
+        Ok(resp.body(Body::from(self.body.clone()))?)
+    }
+}
+
+/// Answers requests with canned responses instead of sending them anywhere.
+///
+/// When several responses match a request, they're served in order, the last one repeating once the others
+/// were used up. This is how a cookie rotation or an expiring session is scripted. Requests nothing matches
+/// get a 404.
+pub struct MemoryTransport {
+    responses: Vec<CannedResponse>,
+    served: Mutex<HashSet<usize>>,
+}
+
+impl MemoryTransport {
+    pub fn new(responses: Vec<CannedResponse>) -> Self {
+        Self {
+            responses,
+            served: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Reads the responses from a JSON array of [`CannedResponse`]s.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let responses = serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse canned responses in {}", path.display()))?;
+
+        Ok(Self::new(responses))
+    }
+
+    fn respond(&self, req: &Request<()>, body: &[u8]) -> Result<Response<Body>> {
+        let key = request_key(body);
+        let host = req.uri().host().unwrap_or_default();
+        let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+
+        let matching = self
+            .responses
+            .iter()
+            .enumerate()
+            .filter(|(_, response)| response.matches(req.method(), host, path, key.as_deref()))
+            .collect::<Vec<_>>();
+
+        let mut served = self.served.lock().unwrap_or_else(|err| err.into_inner());
+
+        let Some((index, response)) = matching
+            .iter()
+            .find(|(index, _)| !served.contains(index))
+            .or(matching.last())
+        else {
+            eprintln!("No canned response for {} {host}{path}.", req.method());
+
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(format!(
+                    "no canned response for {} {host}{path}",
+                    req.method()
+                )))?);
+        };
+
+        served.insert(*index);
+
+        response.to_response()
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send(&self, req: Request<Body>) -> ResponseFuture<'_> {
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+
+            self.respond(&Request::from_parts(parts, ()), &body)
+        })
+    }
+}
+
+/// Creates the transport the config asks for. Must be called once at startup, before any request is sent.
+pub fn init() -> Result<()> {
+    let config = config::get();
+
+    let mut transport: Box<dyn Transport> = match &config.upstream.canned_responses {
+        Some(path) => {
+            println!(
+                "Answering upstream requests from {} instead of Vulcan.",
+                path.display()
+            );
+            Box::new(MemoryTransport::load(path)?)
+        }
+        None => Box::new(HttpClient::new(config)?),
+    };
+
+    if let Some(path) = &config.upstream.record {
+        println!("Recording upstream responses to {}.", path.display());
+        transport = Box::new(RecordingTransport::new(transport, path.clone())?);
+    }
+
+    if TRANSPORT.set(transport).is_err() {
+        panic!("Transport initialized twice.");
+    }
+
+    Ok(())
+}
+
+pub fn get() -> &'static dyn Transport {
+    TRANSPORT
+        .get()
+        .expect("Transport used before initialization.")
+        .as_ref()
+}
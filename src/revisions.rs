@@ -0,0 +1,119 @@
+//! Tracks the content of every calendar event between refreshes, so an event that changed gets a higher
+//! `SEQUENCE` and a new `LAST-MODIFIED` and calendar clients update it instead of keeping the old version.
+//!
+//! The revisions are kept in memory and, with a profile's `revisions_file` set, saved there so the sequence
+//! numbers survive restarts.
+
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Events not seen for this long are forgotten.
+const RETENTION_DAYS: i64 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Revision {
+    /// Hex SHA-256 of the event's content.
+    pub hash: String,
+    pub sequence: u32,
+    pub last_modified: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct Revisions {
+    events: HashMap<String, Revision>,
+    path: Option<PathBuf>,
+    changed: bool,
+}
+
+impl Revisions {
+    /// Reads the revisions saved in `path`. A missing or unreadable file is logged and starts over.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let events = path
+            .as_ref()
+            .filter(|path| path.exists())
+            .and_then(|path| {
+                let events = fs::read_to_string(path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|text| Ok(serde_json::from_str(&text)?));
+
+                events
+                    .map_err(|err| {
+                        eprintln!("Failed to read revisions from {}: {err:#}", path.display())
+                    })
+                    .ok()
+            })
+            .unwrap_or_default();
+
+        Self {
+            events,
+            path,
+            changed: false,
+        }
+    }
+
+    /// Returns the revision of the event `uid` with the given content, bumping it if the content changed since
+    /// the last time.
+    pub fn update(&mut self, uid: &str, content: &[u8]) -> Revision {
+        let hash = hex(ring::digest::digest(&ring::digest::SHA256, content).as_ref());
+        let now = Utc::now();
+
+        let revision = self
+            .events
+            .entry(uid.to_owned())
+            .and_modify(|revision| {
+                if revision.hash != hash {
+                    revision.hash.clone_from(&hash);
+                    revision.sequence += 1;
+                    revision.last_modified = now;
+                    self.changed = true;
+                }
+
+                revision.last_seen = now;
+            })
+            .or_insert_with(|| {
+                self.changed = true;
+
+                Revision {
+                    hash,
+                    sequence: 0,
+                    last_modified: now,
+                    last_seen: now,
+                }
+            });
+
+        revision.clone()
+    }
+
+    /// Forgets old events and writes the revisions to the file, if there is one and any event is new or
+    /// changed. Failures are only logged.
+    pub fn save(&mut self) {
+        if !self.changed {
+            return;
+        }
+
+        self.changed = false;
+
+        let cutoff = Utc::now() - Duration::days(RETENTION_DAYS);
+        self.events
+            .retain(|_, revision| revision.last_seen > cutoff);
+
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let result = serde_json::to_string(&self.events)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(fs::write(path, json)?));
+
+        if let Err(err) = result {
+            eprintln!("Failed to write revisions to {}: {err:#}", path.display());
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
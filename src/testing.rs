@@ -0,0 +1,165 @@
+//! Sets up the service for unit tests: a config with a single profile `g1`, and a [`MemoryTransport`] answering
+//! from `fixtures/canned_responses.json`, recorded from the mock for the three weeks from 12.10.2026. Also builds
+//! lessons and weeks for tests that need changes the fixtures don't have.
+//!
+//! [`MemoryTransport`]: crate::requests::transport::MemoryTransport
+
+use std::{fs, sync::Once, thread};
+
+use chrono::{NaiveDate, NaiveTime};
+
+use crate::{
+    config, profiles, requests,
+    week_plan::{Lesson, LessonStatus, WeekPlan},
+};
+
+static INIT: Once = Once::new();
+
+/// Initializes the config, the transport and the profiles, once for all tests.
+pub fn init() {
+    INIT.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("uonetplan-unit-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("failed to create the test directory");
+        fs::write(dir.join("cookie"), "test-cookie").expect("failed to write the cookie");
+
+        let config = format!(
+            r#"
+symbol = "powiatwarszawski"
+school_id = "123456"
+school_mail = "szkola.edu.pl"
+
+[upstream]
+canned_responses = "{fixture}"
+
+[calendar]
+future_weeks = 0
+
+[[profiles]]
+name = "g1"
+cookie_file = "{cookie_file}"
+"#,
+            fixture = concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/fixtures/canned_responses.json"
+            ),
+            cookie_file = dir.join("cookie").display(),
+        );
+
+        let path = dir.join("config.toml");
+        fs::write(&path, config).expect("failed to write the config");
+
+        config::init(config::Config::load(&path).expect("invalid test config"));
+        requests::transport::init().expect("failed to load the canned responses");
+
+        // Tests run inside their own runtimes, which can't block on another future.
+        thread::spawn(|| {
+            tokio::runtime::Runtime::new()
+                .expect("failed to start a runtime")
+                .block_on(profiles::init())
+                .expect("failed to initialize the profiles")
+        })
+        .join()
+        .expect("initializing the profiles panicked");
+
+        let _ = fs::remove_dir_all(&dir);
+    });
+}
+
+pub fn date(month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2026, month, day).unwrap()
+}
+
+/// A regular lesson in slot `number`, 45 minutes long with 10 minute breaks from 8:00.
+pub fn lesson(date: NaiveDate, number: u32, subject: &str) -> Lesson {
+    let start = NaiveTime::from_hms_opt(8, 0, 0).unwrap()
+        + chrono::Duration::minutes(i64::from(number - 1) * 55);
+
+    Lesson {
+        number,
+        date,
+        start,
+        end: start + chrono::Duration::minutes(45),
+        subject: subject.to_owned(),
+        room: None,
+        teacher: None,
+        group: None,
+        status: LessonStatus::Regular,
+        note: None,
+        changes: Vec::new(),
+    }
+}
+
+/// The week from `monday` to Friday with `lessons`.
+pub fn week(monday: NaiveDate, lessons: Vec<Lesson>) -> WeekPlan {
+    WeekPlan {
+        days: (0..5)
+            .map(|day| monday + chrono::Duration::days(day))
+            .collect(),
+        slots: Vec::new(),
+        lessons,
+    }
+}
+
+/// Three weeks of Mondays from 5.10.2026 for the template: mathematics missing in the second week, physics
+/// cancelled in it, chemistry replaced by biology in the third, English and German for the two groups both
+/// replaced by PE for the whole class in the second, and a single extra lesson in the first.
+pub fn template_weeks() -> Vec<WeekPlan> {
+    let status = |mut lesson: Lesson, status| {
+        lesson.status = status;
+        lesson
+    };
+    let group = |mut lesson: Lesson, group: &str| {
+        lesson.group = Some(group.to_owned());
+        lesson
+    };
+
+    [5, 12, 19]
+        .into_iter()
+        .map(|day| {
+            let monday = date(10, day);
+            let mut lessons = Vec::new();
+
+            if day != 12 {
+                lessons.push(lesson(monday, 1, "Matematyka"));
+            }
+
+            lessons.push(match day {
+                12 => status(lesson(monday, 2, "Fizyka"), LessonStatus::Cancelled),
+                _ => lesson(monday, 2, "Fizyka"),
+            });
+
+            if day == 19 {
+                lessons.push(status(lesson(monday, 3, "Chemia"), LessonStatus::Cancelled));
+                lessons.push(status(
+                    lesson(monday, 3, "Biologia"),
+                    LessonStatus::Substitution,
+                ));
+            } else {
+                lessons.push(lesson(monday, 3, "Chemia"));
+            }
+
+            let english = group(lesson(monday, 4, "Język angielski"), "1/2");
+            let german = group(lesson(monday, 4, "Język niemiecki"), "2/2");
+
+            if day == 12 {
+                lessons.push(status(english, LessonStatus::Cancelled));
+                lessons.push(status(german, LessonStatus::Cancelled));
+                lessons.push(status(
+                    lesson(monday, 4, "Wychowanie fizyczne"),
+                    LessonStatus::Substitution,
+                ));
+            } else {
+                lessons.extend([english, german]);
+            }
+
+            if day == 5 {
+                lessons.push(status(
+                    lesson(monday, 6, "Zajęcia wyrównawcze"),
+                    LessonStatus::Substitution,
+                ));
+            }
+
+            week(monday, lessons)
+        })
+        .collect()
+}
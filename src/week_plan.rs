@@ -0,0 +1,723 @@
+//! Parses the `PlanZajec.mvc/Get` grid into typed lessons.
+//!
+//! The grid has a column per day, headed by the day's name and date, and a row per lesson slot, whose first cell
+//! holds the lesson number and hours. Every other cell is either empty or an HTML snippet with one `<div>` per
+//! lesson (several for split groups, or a cancelled lesson next to its substitute), each holding `<span>`s with
+//! the subject, the room and the teacher, and a parenthesized note. Cancelled lessons and substitutions are
+//! marked with the `x-treelabel-inv` and `x-treelabel-zas` classes, and moves with notes like
+//! `(przeniesiona na lekcję 5, 07.09.2022)`; from these every lesson gets a list of [`Change`]s.
+
+use std::fmt;
+
+use chrono::{NaiveDate, NaiveTime};
+use html_parser::{Dom, Node};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::endpoints::WeekPlanData;
+
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("header of column {column} doesn't contain a date: `{header}`")]
+    InvalidHeader { column: usize, header: String },
+    #[error("row {row} doesn't start with a lesson number and its hours: `{cell}`")]
+    InvalidSlot { row: usize, cell: String },
+    #[error("row {row} has a lesson in column {column}, which has no header")]
+    MissingHeader { row: usize, column: usize },
+    #[error("lesson {number} on {date} {reason}: `{cell}`")]
+    InvalidCell {
+        date: NaiveDate,
+        number: u32,
+        reason: String,
+        cell: String,
+    },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct WeekPlan {
+    /// The date of every day column, in order.
+    pub days: Vec<NaiveDate>,
+    pub slots: Vec<Slot>,
+    pub lessons: Vec<Lesson>,
+}
+
+/// A row of the grid: the lesson number and its hours.
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct Slot {
+    pub number: u32,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum LessonStatus {
+    Regular,
+    Cancelled,
+    Substitution,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Lesson {
+    pub number: u32,
+    pub date: NaiveDate,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub subject: String,
+    pub room: Option<String>,
+    /// As Vulcan writes it, surname first.
+    pub teacher: Option<String>,
+    /// The part of the class attending, e.g. `1/2`.
+    pub group: Option<String>,
+    pub status: LessonStatus,
+    /// The parenthesized remark, without the parentheses, e.g. the reason of a cancellation.
+    pub note: Option<String>,
+    pub changes: Vec<Change>,
+}
+
+/// How a lesson differs from the regular timetable.
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Change {
+    Cancelled {
+        reason: Option<String>,
+    },
+    TeacherSubstituted {
+        original: Option<String>,
+        substitute: Option<String>,
+    },
+    RoomChanged {
+        original: Option<String>,
+        new: Option<String>,
+    },
+    /// The lesson was moved here from another slot.
+    MovedFrom {
+        date: NaiveDate,
+        lesson: u32,
+    },
+    /// The lesson was moved to another slot and doesn't take place here.
+    MovedTo {
+        date: NaiveDate,
+        lesson: u32,
+    },
+    /// An extra lesson, not replacing anything.
+    Added,
+}
+
+impl Change {
+    /// A short Polish name of the kind of change, for calendar event categories.
+    pub fn category(&self) -> &'static str {
+        match self {
+            Change::Cancelled { .. } => "Odwołana",
+            Change::TeacherSubstituted { .. } => "Zastępstwo",
+            Change::RoomChanged { .. } => "Zmiana sali",
+            Change::MovedFrom { .. } | Change::MovedTo { .. } => "Przeniesiona",
+            Change::Added => "Dodatkowa lekcja",
+        }
+    }
+}
+
+/// Describes the change in Polish, like Vulcan does, for calendar event descriptions.
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Cancelled { reason: None } => write!(f, "Odwołana"),
+            Change::Cancelled {
+                reason: Some(reason),
+            } => write!(f, "Odwołana: {reason}"),
+            Change::TeacherSubstituted {
+                original,
+                substitute,
+            } => {
+                write!(f, "Zastępstwo")?;
+
+                if let Some(substitute) = substitute {
+                    write!(f, ": {substitute}")?;
+                }
+
+                match original {
+                    Some(original) => write!(f, " (za {original})"),
+                    None => Ok(()),
+                }
+            }
+            Change::RoomChanged {
+                original: Some(original),
+                new: Some(new),
+            } => write!(f, "Zmiana sali: {original} → {new}"),
+            Change::RoomChanged { new: Some(new), .. } => write!(f, "Zmiana sali na {new}"),
+            Change::RoomChanged { new: None, .. } => write!(f, "Zmiana sali"),
+            Change::MovedFrom { date, lesson } => write!(
+                f,
+                "Przeniesiona z lekcji {lesson}, {}",
+                date.format("%d.%m.%Y")
+            ),
+            Change::MovedTo { date, lesson } => write!(
+                f,
+                "Przeniesiona na lekcję {lesson}, {}",
+                date.format("%d.%m.%Y")
+            ),
+            Change::Added => write!(f, "Dodatkowa lekcja"),
+        }
+    }
+}
+
+impl Lesson {
+    /// The changes, followed by the note unless a change already covers it.
+    pub fn description(&self) -> Option<String> {
+        let mut lines = self
+            .changes
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        let note_described = self
+            .changes
+            .iter()
+            .any(|change| matches!(change, Change::Cancelled { reason: Some(_) }))
+            || self.note.as_deref().and_then(parse_annotation).is_some();
+
+        if let Some(note) = self.note.as_ref().filter(|_| !note_described) {
+            lines.push(note.clone());
+        }
+
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    /// The subject followed by the group, as Vulcan shows it.
+    pub fn title(&self) -> String {
+        match &self.group {
+            Some(group) => format!("{} [{group}]", self.subject),
+            None => self.subject.clone(),
+        }
+    }
+
+    /// Whether this is a cancelled lesson and `other` a substitution in its slot, for the same group or the whole
+    /// class.
+    pub fn is_replaced_by(&self, other: &Lesson) -> bool {
+        self.status == LessonStatus::Cancelled
+            && other.status == LessonStatus::Substitution
+            && (other.date, other.number) == (self.date, self.number)
+            && (other.group.is_none() || self.group.is_none() || other.group == self.group)
+    }
+}
+
+pub fn parse(data: &WeekPlanData) -> Result<WeekPlan, ParseError> {
+    let days = data
+        .headers
+        .iter()
+        .enumerate()
+        // The first column holds the lesson hours.
+        .skip(1)
+        .map(|(column, header)| {
+            parse_date(&header.text).ok_or_else(|| ParseError::InvalidHeader {
+                column,
+                header: header.text.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut slots = Vec::new();
+    let mut lessons = Vec::new();
+
+    for (row_index, row) in data.rows.iter().enumerate() {
+        let Some((slot_cell, cells)) = row.split_first() else {
+            continue;
+        };
+
+        let slot = parse_slot(slot_cell).ok_or_else(|| ParseError::InvalidSlot {
+            row: row_index,
+            cell: slot_cell.clone(),
+        })?;
+
+        slots.push(slot);
+
+        for (column, cell) in cells.iter().enumerate() {
+            if cell.trim().is_empty() {
+                continue;
+            }
+
+            let date = *days.get(column).ok_or(ParseError::MissingHeader {
+                row: row_index,
+                column: column + 1,
+            })?;
+
+            let invalid = |reason: &str| ParseError::InvalidCell {
+                date,
+                number: slot.number,
+                reason: reason.to_owned(),
+                cell: cell.clone(),
+            };
+
+            let dom =
+                Dom::parse(cell).map_err(|err| invalid(&format!("isn't valid HTML ({err})")))?;
+
+            let cell_lessons = entries(&dom.children)
+                .into_iter()
+                .filter(|entry| entry.iter().any(|node| !node_text(node).is_empty()))
+                .map(|entry| parse_lesson(entry, date, slot))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(invalid)?;
+
+            if cell_lessons.is_empty() {
+                return Err(invalid("has no lessons"));
+            }
+
+            lessons.extend(describe_changes(cell_lessons));
+        }
+    }
+
+    Ok(WeekPlan {
+        days,
+        slots,
+        lessons,
+    })
+}
+
+/// Splits cell text into words, treating tags like `<br />` as whitespace.
+fn words(html: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let (text, after) = match rest.find('<') {
+            Some(start) => (
+                &rest[..start],
+                rest[start..]
+                    .find('>')
+                    .map_or("", |end| &rest[start + end + 1..]),
+            ),
+            None => (rest, ""),
+        };
+
+        words.extend(text.split_whitespace());
+        rest = after;
+    }
+
+    words
+}
+
+/// Finds the `dd.mm.yyyy` date in a header like `Poniedziałek<br />05.09.2022`.
+fn parse_date(header: &str) -> Option<NaiveDate> {
+    words(header)
+        .into_iter()
+        .find_map(|word| NaiveDate::parse_from_str(word, "%d.%m.%Y").ok())
+}
+
+/// Reads a slot cell like `1<br />08:00<br />08:45` (or `1. 08:00-08:45`).
+fn parse_slot(cell: &str) -> Option<Slot> {
+    let words = words(cell);
+    let (number, rest) = words.split_first()?;
+
+    let mut times = rest
+        .iter()
+        .flat_map(|word| word.split('-'))
+        .filter_map(|word| NaiveTime::parse_from_str(word, "%H:%M").ok());
+
+    Some(Slot {
+        number: number.trim_end_matches('.').parse().ok()?,
+        start: times.next()?,
+        end: times.next()?,
+    })
+}
+
+/// The text of a node and its descendants, with whitespace collapsed.
+fn node_text(node: &Node) -> String {
+    match node {
+        Node::Text(text) => text.split_whitespace().collect::<Vec<_>>().join(" "),
+        Node::Element(element) => element
+            .children
+            .iter()
+            .map(node_text)
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "),
+        Node::Comment(_) => String::new(),
+    }
+}
+
+/// Splits a cell into its lesson entries: every innermost `<div>`, or the whole cell if it has no `<div>`s.
+fn entries(nodes: &[Node]) -> Vec<&[Node]> {
+    let divs = nodes
+        .iter()
+        .filter_map(Node::element)
+        .filter(|element| element.name == "div")
+        .collect::<Vec<_>>();
+
+    if divs.is_empty() {
+        return vec![nodes];
+    }
+
+    divs.into_iter()
+        .flat_map(|div| entries(&div.children))
+        .collect()
+}
+
+fn strip_delimiters(text: &str, open: char, close: char) -> Option<&str> {
+    Some(text.strip_prefix(open)?.strip_suffix(close)?.trim())
+}
+
+/// Which fields of an entry Vulcan marked as changed with `x-treelabel-zas`. None are when the whole entry is
+/// marked, as that makes it a different lesson rather than a changed one.
+#[derive(Default)]
+struct Marks {
+    room: bool,
+    teacher: bool,
+}
+
+/// Reads one lesson entry: the subject, room and teacher `<span>`s (in this order, skipping empty ones), a
+/// `[group]` and a `(note)`.
+fn parse_lesson(
+    entry: &[Node],
+    date: NaiveDate,
+    slot: Slot,
+) -> Result<(Lesson, Marks), &'static str> {
+    let mut fields = Vec::new();
+    let mut group = None;
+    let mut note = None;
+    let mut status = LessonStatus::Regular;
+
+    for child in entry {
+        let has_class = |name: &str| {
+            child
+                .element()
+                .is_some_and(|element| element.classes.iter().any(|class| class == name))
+        };
+
+        let changed = has_class("x-treelabel-zas");
+
+        if has_class("x-treelabel-inv") {
+            status = LessonStatus::Cancelled;
+        } else if changed && status == LessonStatus::Regular {
+            status = LessonStatus::Substitution;
+        }
+
+        let text = node_text(child);
+
+        if text.is_empty() {
+            continue;
+        }
+
+        if let Some(text) = strip_delimiters(&text, '(', ')') {
+            note = Some(text.to_owned());
+        } else if let Some(text) = strip_delimiters(&text, '[', ']') {
+            group = Some(text.to_owned());
+        } else {
+            fields.push((text, changed));
+        }
+    }
+
+    let mut fields = fields.into_iter();
+    let (mut subject, subject_changed) = fields.next().ok_or("has no subject")?;
+    let room = fields.next();
+    let teacher = fields.next();
+
+    // The group usually trails the subject, as in `Język angielski [1/2]`.
+    if let Some((name, rest)) = subject.split_once(" [") {
+        if let Some(subject_group) = rest.strip_suffix(']') {
+            group = Some(subject_group.trim().to_owned());
+            subject = name.trim().to_owned();
+        }
+    }
+
+    let marks = Marks {
+        room: !subject_changed && room.as_ref().is_some_and(|(_, changed)| *changed),
+        teacher: !subject_changed && teacher.as_ref().is_some_and(|(_, changed)| *changed),
+    };
+
+    let lesson = Lesson {
+        number: slot.number,
+        date,
+        start: slot.start,
+        end: slot.end,
+        subject,
+        room: room.map(|(room, _)| room),
+        teacher: teacher.map(|(teacher, _)| teacher),
+        group,
+        status,
+        note,
+        changes: Vec::new(),
+    };
+
+    Ok((lesson, marks))
+}
+
+/// Reads the remarks Vulcan adds to moved and substituted lessons: `przeniesiona z lekcji 3, 05.09.2022`,
+/// `przeniesiona na lekcję 5, 07.09.2022`, `zastępstwo za: Kowalski Jan` and `zastępstwo: Nowak Anna`.
+pub fn parse_annotation(note: &str) -> Option<Change> {
+    let lowercase = note.to_lowercase();
+
+    if let Some(rest) = lowercase
+        .strip_prefix("przeniesiona ")
+        .or_else(|| lowercase.strip_prefix("przesunięta "))
+    {
+        let words = rest
+            .split(|char: char| char.is_whitespace() || char == ',')
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>();
+
+        let lesson = words.iter().find_map(|word| word.parse().ok())?;
+        let date = words
+            .iter()
+            .find_map(|word| NaiveDate::parse_from_str(word, "%d.%m.%Y").ok())?;
+
+        return match words.first() {
+            Some(&"z") => Some(Change::MovedFrom { date, lesson }),
+            Some(&"na") => Some(Change::MovedTo { date, lesson }),
+            _ => None,
+        };
+    }
+
+    let name = |prefix: &str| {
+        strip_prefix_ignoring_case(note, prefix)
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_owned)
+    };
+
+    if let Some(original) = name("zastępstwo za:") {
+        return Some(Change::TeacherSubstituted {
+            original: Some(original),
+            substitute: None,
+        });
+    }
+
+    if let Some(substitute) = name("zastępstwo:") {
+        return Some(Change::TeacherSubstituted {
+            original: None,
+            substitute: Some(substitute),
+        });
+    }
+
+    None
+}
+
+/// `text` without `prefix`, compared case-insensitively, keeping the original case of the rest.
+fn strip_prefix_ignoring_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let mut chars = text.char_indices();
+
+    for expected in prefix.chars() {
+        let (_, char) = chars.next()?;
+
+        if !char.to_lowercase().eq(expected.to_lowercase()) {
+            return None;
+        }
+    }
+
+    Some(chars.as_str())
+}
+
+/// Works out the changes of the lessons sharing a cell. A substitution is compared against the cancelled
+/// lesson next to it, if there is one.
+fn describe_changes(cell: Vec<(Lesson, Marks)>) -> Vec<Lesson> {
+    let original = cell
+        .iter()
+        .map(|(lesson, _)| lesson)
+        .find(|lesson| {
+            lesson.status == LessonStatus::Cancelled
+                && !matches!(
+                    lesson.note.as_deref().and_then(parse_annotation),
+                    Some(Change::MovedTo { .. })
+                )
+        })
+        .cloned();
+
+    cell.into_iter()
+        .map(|(mut lesson, marks)| {
+            let annotation = lesson.note.as_deref().and_then(parse_annotation);
+
+            lesson.changes = match lesson.status {
+                LessonStatus::Cancelled => match annotation {
+                    Some(moved @ Change::MovedTo { .. }) => vec![moved],
+                    _ => vec![Change::Cancelled {
+                        reason: lesson.note.clone(),
+                    }],
+                },
+                LessonStatus::Substitution => {
+                    substitution_changes(&lesson, &marks, original.as_ref(), annotation)
+                }
+                LessonStatus::Regular => annotation
+                    .filter(|annotation| matches!(annotation, Change::MovedFrom { .. }))
+                    .into_iter()
+                    .collect(),
+            };
+
+            lesson
+        })
+        .collect()
+}
+
+fn substitution_changes(
+    lesson: &Lesson,
+    marks: &Marks,
+    original: Option<&Lesson>,
+    annotation: Option<Change>,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    let annotated_teacher = match annotation {
+        Some(moved @ Change::MovedFrom { .. }) => {
+            changes.push(moved);
+            None
+        }
+        Some(Change::TeacherSubstituted { original, .. }) => original,
+        _ => None,
+    };
+
+    let original_teacher = original
+        .and_then(|original| original.teacher.clone())
+        .or(annotated_teacher);
+
+    if marks.teacher || original_teacher.is_some() && original_teacher != lesson.teacher {
+        changes.push(Change::TeacherSubstituted {
+            original: original_teacher,
+            substitute: lesson.teacher.clone(),
+        });
+    }
+
+    match original {
+        Some(original) if original.room != lesson.room => changes.push(Change::RoomChanged {
+            original: original.room.clone(),
+            new: lesson.room.clone(),
+        }),
+        None if marks.room => changes.push(Change::RoomChanged {
+            original: None,
+            new: lesson.room.clone(),
+        }),
+        _ => {}
+    }
+
+    if changes.is_empty() && original.is_none() {
+        changes.push(Change::Added);
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{endpoints, profiles, testing};
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, day).unwrap()
+    }
+
+    async fn fetch() -> WeekPlan {
+        testing::init();
+
+        let profile = profiles::find("g1").unwrap();
+        let auth_info = profile.auth.lock().await;
+
+        let response = endpoints::get_week_plan(date(12), &auth_info)
+            .await
+            .unwrap();
+
+        parse(&response.data).unwrap()
+    }
+
+    fn lesson(week: &WeekPlan, day: u32, number: u32, subject: &str) -> Lesson {
+        week.lessons
+            .iter()
+            .find(|lesson| {
+                (lesson.date, lesson.number, lesson.subject.as_str())
+                    == (date(day), number, subject)
+            })
+            .unwrap_or_else(|| panic!("no {subject} in lesson {number} on {day}.10"))
+            .clone()
+    }
+
+    #[test]
+    fn parses_annotations_in_any_case() {
+        assert_eq!(
+            parse_annotation("ZASTĘPSTWO ZA: Wójcik Marek"),
+            Some(Change::TeacherSubstituted {
+                original: Some("Wójcik Marek".to_owned()),
+                substitute: None,
+            })
+        );
+        assert_eq!(
+            parse_annotation("Zastępstwo: Zielińska Ewa"),
+            Some(Change::TeacherSubstituted {
+                original: None,
+                substitute: Some("Zielińska Ewa".to_owned()),
+            })
+        );
+        assert_eq!(parse_annotation("İzastępstwo: Zielińska Ewa"), None);
+        assert_eq!(parse_annotation("zastępstwo:"), None);
+    }
+
+    #[tokio::test]
+    async fn parses_the_fetched_week() {
+        let week = fetch().await;
+
+        assert_eq!(week.days, (12..=16).map(date).collect::<Vec<_>>());
+        assert_eq!(week.slots.len(), 6);
+        assert_eq!(
+            (week.slots[0].start, week.slots[0].end),
+            (
+                NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(8, 45, 0).unwrap()
+            )
+        );
+
+        let polish = lesson(&week, 13, 1, "Język polski");
+        assert_eq!(polish.status, LessonStatus::Regular);
+        assert_eq!(polish.room.as_deref(), Some("8"));
+        assert_eq!(polish.teacher.as_deref(), Some("Lewandowska Maria"));
+        assert!(polish.changes.is_empty());
+
+        let english = lesson(&week, 12, 3, "Język angielski");
+        assert_eq!(english.group.as_deref(), Some("1/2"));
+        assert_eq!(
+            lesson(&week, 12, 3, "Język niemiecki").group.as_deref(),
+            Some("2/2")
+        );
+    }
+
+    #[tokio::test]
+    async fn parses_the_changes_of_the_fetched_week() {
+        let week = fetch().await;
+
+        let moved = lesson(&week, 12, 1, "Matematyka");
+        assert_eq!(moved.status, LessonStatus::Cancelled);
+        assert_eq!(
+            moved.changes,
+            [Change::MovedTo {
+                date: date(15),
+                lesson: 6
+            }]
+        );
+
+        let moved_here = lesson(&week, 15, 6, "Matematyka");
+        assert_eq!(moved_here.status, LessonStatus::Substitution);
+        assert_eq!(
+            moved_here.changes,
+            [Change::MovedFrom {
+                date: date(12),
+                lesson: 1
+            }]
+        );
+
+        let cancelled = lesson(&week, 13, 2, "Fizyka");
+        assert_eq!(cancelled.status, LessonStatus::Cancelled);
+        assert_eq!(cancelled.note.as_deref(), Some("oddział nieobecny"));
+
+        let substitution = lesson(&week, 13, 3, "Chemia");
+        assert_eq!(substitution.status, LessonStatus::Substitution);
+        assert!(substitution.changes.contains(&Change::TeacherSubstituted {
+            original: Some("Wójcik Marek".to_owned()),
+            substitute: Some("Zielińska Ewa".to_owned()),
+        }));
+
+        assert_eq!(
+            lesson(&week, 13, 6, "Zajęcia wyrównawcze").changes,
+            [Change::Added]
+        );
+        assert!(lesson(&week, 12, 5, "Historia")
+            .changes
+            .contains(&Change::RoomChanged {
+                original: None,
+                new: Some("15".to_owned())
+            }));
+    }
+}
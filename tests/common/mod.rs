@@ -0,0 +1,232 @@
+//! Runs `uonetplan mock` and the service as child processes on free ports and talks to them over plain HTTP, so
+//! the tests exercise the same binary that's deployed.
+
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    process::{Child, Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const BINARY: &str = env!("CARGO_BIN_EXE_uonetplan");
+
+/// A port nothing listens on right now.
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("no free port")
+        .port()
+}
+
+/// Polls `condition` until it holds, panicking after `timeout`.
+pub fn wait_for(what: &str, timeout: Duration, mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + timeout;
+
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out waiting for {what}");
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Sends a request without a body and reads the whole response.
+pub fn request(port: u16, method: &str, path: &str) -> Option<Response> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .ok()?;
+
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: 127.0.0.1:{port}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+    )
+    .ok()?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+
+    let (head, body) = response.split_once("\r\n\r\n")?;
+    let status = head.split(' ').nth(1)?.parse().ok()?;
+
+    Some(Response {
+        status,
+        body: body.to_owned(),
+    })
+}
+
+pub fn get(port: u16, path: &str) -> Response {
+    request(port, "GET", path).unwrap_or_else(|| panic!("GET {path} failed"))
+}
+
+/// A child process that's killed when dropped.
+struct Process(Child);
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+pub struct Mock {
+    pub port: u16,
+    _process: Process,
+}
+
+impl Mock {
+    /// Starts the mock with its default credentials (`jan.kowalski`/`haslo`) and the given extra arguments.
+    pub fn start(args: &[&str]) -> Self {
+        let port = free_port();
+
+        let child = Command::new(BINARY)
+            .args(["mock", "--bind", &format!("127.0.0.1:{port}")])
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("failed to start the mock");
+
+        let mock = Self {
+            port,
+            _process: Process(child),
+        };
+
+        wait_for("the mock to start", Duration::from_secs(10), || {
+            TcpStream::connect(("127.0.0.1", port)).is_ok()
+        });
+
+        mock
+    }
+}
+
+/// A directory with a config for a single profile `g1` using the mock, and its cookie file.
+pub struct Setup {
+    pub dir: PathBuf,
+    pub port: u16,
+}
+
+impl Setup {
+    /// Writes the config, with `profile` appended to the `[[profiles]]` entry and `extra` after it.
+    pub fn new(name: &str, mock: &Mock, profile: &str, extra: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("uonetplan-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("failed to create the test directory");
+
+        let port = free_port();
+
+        let config = format!(
+            r#"
+symbol = "powiatwarszawski"
+school_id = "123456"
+school_mail = "szkola.edu.pl"
+bind = "127.0.0.1:{port}"
+
+[upstream]
+base_url = "http://127.0.0.1:{mock_port}"
+
+[retry]
+max_attempts = 1
+
+[[profiles]]
+name = "g1"
+cookie_file = "{cookie_file}"
+{profile}
+
+{extra}
+"#,
+            mock_port = mock.port,
+            cookie_file = dir.join("cookie").display(),
+        );
+
+        fs::write(dir.join("config.toml"), config).expect("failed to write the config");
+
+        Self { dir, port }
+    }
+
+    pub fn cookie(&self) -> Option<String> {
+        fs::read_to_string(self.dir.join("cookie")).ok()
+    }
+
+    pub fn set_cookie(&self, cookie: &str) {
+        fs::write(self.dir.join("cookie"), cookie).expect("failed to write the cookie");
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(BINARY);
+        command
+            .arg("--config")
+            .arg(self.dir.join("config.toml"))
+            .env("RUST_BACKTRACE", "0");
+
+        command
+    }
+
+    /// What the service started by [`Setup::serve`] printed so far.
+    pub fn log(&self) -> String {
+        fs::read_to_string(self.dir.join("service.log")).unwrap_or_default()
+    }
+
+    /// Starts the service, with its output going to [`Setup::log`], and waits until it answers.
+    pub fn serve(&self) -> Service {
+        let log = fs::File::create(self.dir.join("service.log")).expect("failed to create the log");
+
+        let child = self
+            .command()
+            .stdout(log)
+            .spawn()
+            .expect("failed to start the service");
+
+        let service = Service {
+            port: self.port,
+            _process: Process(child),
+        };
+
+        wait_for("the service to start", Duration::from_secs(20), || {
+            request(self.port, "GET", "/status").is_some()
+        });
+
+        service
+    }
+
+    /// Runs the service expecting it to exit on its own, e.g. because it can't start.
+    pub fn run_to_exit(&self) -> Output {
+        let mut child = self
+            .command()
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to start the service");
+
+        let deadline = Instant::now() + Duration::from_secs(20);
+
+        while child.try_wait().expect("failed to wait").is_none() {
+            if Instant::now() > deadline {
+                let _ = child.kill();
+                panic!("the service didn't exit");
+            }
+
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        child.wait_with_output().expect("failed to read the output")
+    }
+}
+
+impl Drop for Setup {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub struct Service {
+    pub port: u16,
+    _process: Process,
+}
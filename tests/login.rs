@@ -0,0 +1,65 @@
+//! Logging in with a login and a password against the mock's CUFS pages.
+
+mod common;
+
+use common::{get, Mock, Setup};
+
+#[test]
+fn logs_in_through_the_redirect_chain_without_a_cookie() {
+    let mock = Mock::start(&[]);
+    let setup = Setup::new(
+        "login",
+        &mock,
+        r#"login = "jan.kowalski"
+password = "haslo""#,
+        "",
+    );
+
+    let _service = setup.serve();
+
+    // LoginEndpoint.aspx → CUFS sign-in URL → login form → WS-Federation form → LoginEndpoint.aspx.
+    let cookie = setup.cookie().expect("the cookie file wasn't written");
+    assert!(cookie.starts_with("mock-session-"), "got cookie {cookie}");
+
+    let calendar = get(setup.port, "/g1/plan.ics");
+    assert_eq!(calendar.status, 200, "{}", calendar.body);
+    assert!(calendar.body.contains("BEGIN:VEVENT"));
+}
+
+#[test]
+fn reports_invalid_credentials() {
+    let mock = Mock::start(&[]);
+    let setup = Setup::new(
+        "invalid-login",
+        &mock,
+        r#"login = "jan.kowalski"
+password = "zle-haslo""#,
+        "",
+    );
+
+    let output = setup.run_to_exit();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("invalid login or password: Zła nazwa użytkownika lub hasło."),
+        "{stderr}"
+    );
+    assert_eq!(setup.cookie(), None);
+}
+
+#[test]
+fn accepts_the_credentials_the_mock_is_started_with() {
+    let mock = Mock::start(&["--login", "anna.nowak", "--password", "tajne"]);
+    let setup = Setup::new(
+        "custom-login",
+        &mock,
+        r#"login = "anna.nowak"
+password = "tajne""#,
+        "",
+    );
+
+    let _service = setup.serve();
+
+    assert_eq!(get(setup.port, "/g1/plan").status, 200);
+}
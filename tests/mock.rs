@@ -0,0 +1,84 @@
+//! The session refresher against the mock's expiring sessions.
+
+mod common;
+
+use std::time::Duration;
+
+use common::{get, request, wait_for, Mock, Setup};
+
+#[test]
+fn recovers_an_expired_session() {
+    let mock = Mock::start(&[]);
+    let setup = Setup::new(
+        "expire",
+        &mock,
+        r#"login = "jan.kowalski"
+password = "haslo""#,
+        r#"
+[session]
+refresh_interval = 1
+first_retry_delay = 1
+"#,
+    );
+
+    setup.set_cookie("mock-session");
+    let service = setup.serve();
+
+    assert_eq!(get(service.port, "/g1/plan.ics").status, 200);
+
+    let expired = request(mock.port, "POST", "/mock/expire").expect("POST /mock/expire failed");
+    assert_eq!(expired.status, 204);
+
+    wait_for(
+        "the refresher to recover the session",
+        Duration::from_secs(20),
+        || setup.log().contains("Recovered the session of profile g1."),
+    );
+
+    let cookie = setup.cookie().expect("the cookie file is gone");
+    assert!(cookie.starts_with("mock-session-"), "got cookie {cookie}");
+
+    let calendar = get(service.port, "/g1/plan.ics?past_weeks=1");
+    assert_eq!(calendar.status, 200, "{}", calendar.body);
+    assert!(calendar.body.contains("BEGIN:VEVENT"));
+
+    let session = get(service.port, "/g1/session");
+    assert!(
+        session.body.contains(r#""state":"active""#),
+        "{}",
+        session.body
+    );
+}
+
+#[test]
+fn starts_with_a_stale_cookie_and_credentials() {
+    let mock = Mock::start(&[]);
+    let setup = Setup::new(
+        "stale",
+        &mock,
+        r#"login = "jan.kowalski"
+password = "haslo""#,
+        "",
+    );
+
+    setup.set_cookie("bogus");
+    let service = setup.serve();
+
+    assert!(
+        setup.log().contains("Recovered the session of profile g1."),
+        "{}",
+        setup.log()
+    );
+    assert_ne!(setup.cookie().as_deref(), Some("bogus"));
+
+    let calendar = get(service.port, "/g1/plan.ics");
+    assert_eq!(calendar.status, 200, "{}", calendar.body);
+    assert!(calendar.body.contains("BEGIN:VEVENT"));
+
+    let students = get(service.port, "/g1/students");
+    assert!(
+        students.body.contains(r#""selected":true"#),
+        "{}",
+        students.body
+    );
+}